@@ -12,7 +12,9 @@ pub fn parser_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("sample-size-example");
     group.significance_level(0.1).sample_size(10);
 
-    group.bench_function("parse 5000 lines", |b| b.iter(|| Parser::new(&code)));
+    group.bench_function("parse 5000 lines", |b| {
+        b.iter(|| Parser::new(&code).unwrap())
+    });
 }
 
 criterion_group!(benches, parser_benchmark);
@@ -8,7 +8,7 @@ fn main() {
         coolFunc(coolInstance.hey);
     "#;
 
-    let parser = Parser::new(&code);
+    let parser = Parser::new(code).unwrap();
 
     let mut scope = Scope::default();
     scope.setup_globals();
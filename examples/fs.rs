@@ -20,7 +20,7 @@ fn main() {
         });
     "#;
 
-    let parser = Arc::new(Parser::new(&code));
+    let parser = Arc::new(Parser::new(code).unwrap());
 
     Runtime::evaluate(&parser).unwrap();
 }
@@ -29,7 +29,7 @@ fn main() {
         DefaultPromptSegment::Empty,
     );
 
-    let mut parser = Parser::new("");
+    let mut parser = Parser::new("").unwrap();
 
     let mut scope = Scope::default();
     scope.setup_globals();
@@ -41,7 +41,12 @@ fn main() {
         let sig = line_editor.read_line(&prompt);
         match sig {
             Ok(Signal::Success(buffer)) => {
-                parser.parse(&buffer);
+                if let Err(errors) = parser.parse(&buffer) {
+                    for err in errors {
+                        println!("{}", Style::new().fg(Color::Red).paint(err.to_string()));
+                    }
+                    continue;
+                }
 
                 execution =
                     Runtime::resume_execution(&mut scope, &parser, execution.scope_position);
@@ -56,7 +61,7 @@ fn main() {
                 } else if let Err(err) = execution.result {
                     println!(
                         "Error: {}",
-                        Style::new().fg(Color::Red).paint(format!("{err:?}"))
+                        Style::new().fg(Color::Red).paint(err.to_string())
                     );
                 }
             }
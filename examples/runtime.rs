@@ -27,7 +27,7 @@ fn main() {
         println(value);
     "#;
 
-    let parser = Parser::new(&code);
+    let parser = Parser::new(code).unwrap();
 
     Runtime::evaluate(&parser).unwrap();
 }
@@ -12,13 +12,13 @@ fn main() {
         };
     "#;
 
-    let mut parser = Parser::new(&code);
+    let mut parser = Parser::new(code).unwrap();
 
     let code = r#"
         hey("marc");
     "#;
 
-    parser.parse(code);
+    parser.parse(code).unwrap();
 
     Runtime::evaluate(&Arc::new(parser)).unwrap();
 }
@@ -15,7 +15,7 @@ fn main() {
     "#
     .repeat(10000000);
 
-    Parser::new(&code);
+    Parser::new(&code).unwrap();
 
     println!("{}s", now.elapsed().as_secs_f32());
 }
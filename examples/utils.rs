@@ -13,6 +13,6 @@ static CODE: &str = r#"
 "#;
 
 fn main() {
-    let parser = Arc::new(Parser::new(CODE));
+    let parser = Arc::new(Parser::new(CODE).unwrap());
     Runtime::evaluate(&parser).unwrap();
 }
@@ -1,14 +1,714 @@
+pub mod lexer {
+    use std::{fmt::Display, iter::Peekable, str::CharIndices};
+
+    use crate::parser::{FileId, ParseError, ParseErrorKind, Span, MAX_NESTING_DEPTH};
+
+    /// Words with a special meaning, they can't be used as identifiers
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Keyword {
+        Let,
+        Fn,
+        If,
+        Else,
+        While,
+        For,
+        In,
+        Break,
+        Continue,
+        Return,
+        Struct,
+        Enum,
+        Match,
+        Try,
+        Catch,
+        True,
+        False,
+    }
+
+    impl Keyword {
+        pub fn from_word(word: &str) -> Option<Self> {
+            match word {
+                "let" => Some(Self::Let),
+                "fn" => Some(Self::Fn),
+                "if" => Some(Self::If),
+                "else" => Some(Self::Else),
+                "while" => Some(Self::While),
+                "for" => Some(Self::For),
+                "in" => Some(Self::In),
+                "break" => Some(Self::Break),
+                "continue" => Some(Self::Continue),
+                "return" => Some(Self::Return),
+                "struct" => Some(Self::Struct),
+                "enum" => Some(Self::Enum),
+                "match" => Some(Self::Match),
+                "try" => Some(Self::Try),
+                "catch" => Some(Self::Catch),
+                "true" => Some(Self::True),
+                "false" => Some(Self::False),
+                _ => None,
+            }
+        }
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Let => "let",
+                Self::Fn => "fn",
+                Self::If => "if",
+                Self::Else => "else",
+                Self::While => "while",
+                Self::For => "for",
+                Self::In => "in",
+                Self::Break => "break",
+                Self::Continue => "continue",
+                Self::Return => "return",
+                Self::Struct => "struct",
+                Self::Enum => "enum",
+                Self::Match => "match",
+                Self::Try => "try",
+                Self::Catch => "catch",
+                Self::True => "true",
+                Self::False => "false",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Punctuation {
+        OpenParen,
+        CloseParen,
+        OpenBrace,
+        CloseBrace,
+        OpenBracket,
+        CloseBracket,
+        Semicolon,
+        Comma,
+        Dot,
+        Colon,
+        Equal,
+    }
+
+    impl Punctuation {
+        pub fn as_char(&self) -> char {
+            match self {
+                Self::OpenParen => '(',
+                Self::CloseParen => ')',
+                Self::OpenBrace => '{',
+                Self::CloseBrace => '}',
+                Self::OpenBracket => '[',
+                Self::CloseBracket => ']',
+                Self::Semicolon => ';',
+                Self::Comma => ',',
+                Self::Dot => '.',
+                Self::Colon => ':',
+                Self::Equal => '=',
+            }
+        }
+
+        /// The delimiter that closes this one, if it opens a group
+        pub fn closing(&self) -> Option<Self> {
+            match self {
+                Self::OpenParen => Some(Self::CloseParen),
+                Self::OpenBrace => Some(Self::CloseBrace),
+                Self::OpenBracket => Some(Self::CloseBracket),
+                _ => None,
+            }
+        }
+
+        pub fn is_closing(&self) -> bool {
+            matches!(
+                self,
+                Self::CloseParen | Self::CloseBrace | Self::CloseBracket
+            )
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Operator {
+        Plus,
+        Minus,
+        /// A `-` attached to the next operand but not to the previous one, such as `a -b`
+        Negate,
+        Star,
+        Slash,
+        Percent,
+        EqualEqual,
+        BangEqual,
+        Less,
+        LessEqual,
+        Greater,
+        GreaterEqual,
+        Bang,
+        AmpAmp,
+        PipePipe,
+        /// `=>`, between the pattern and the value of a match arm
+        FatArrow,
+        /// `?`, after a value to propagate its `Err` or `None`
+        Question,
+        PlusEqual,
+        MinusEqual,
+        StarEqual,
+        SlashEqual,
+        PercentEqual,
+    }
+
+    impl Operator {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Plus => "+",
+                Self::Minus | Self::Negate => "-",
+                Self::Star => "*",
+                Self::Slash => "/",
+                Self::Percent => "%",
+                Self::EqualEqual => "==",
+                Self::BangEqual => "!=",
+                Self::Less => "<",
+                Self::LessEqual => "<=",
+                Self::Greater => ">",
+                Self::GreaterEqual => ">=",
+                Self::Bang => "!",
+                Self::AmpAmp => "&&",
+                Self::PipePipe => "||",
+                Self::FatArrow => "=>",
+                Self::Question => "?",
+                Self::PlusEqual => "+=",
+                Self::MinusEqual => "-=",
+                Self::StarEqual => "*=",
+                Self::SlashEqual => "/=",
+                Self::PercentEqual => "%=",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CommentKind {
+        /// `// comment`
+        Line,
+        /// `/* comment */`
+        Block,
+    }
+
+    /// Piece of a [`Token::Template`]
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum TemplatePart {
+        Text(String),
+        /// Code inside `${}`, starting at the byte `offset`
+        Expression {
+            code: String,
+            offset: usize,
+        },
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Token {
+        Identifier(String),
+        Keyword(Keyword),
+        Number(usize),
+        /// Negative integer
+        Integer(i64),
+        Float(f64),
+        String(String),
+        /// String literal with embedded `${expression}`s
+        Template(Vec<TemplatePart>),
+        Punctuation(Punctuation),
+        Operator(Operator),
+        /// Text of a comment, without its delimiters
+        Comment(CommentKind, String),
+    }
+
+    impl Token {
+        /// Whether the token has no meaning for the program, such as comments
+        pub fn is_trivia(&self) -> bool {
+            matches!(self, Self::Comment(..))
+        }
+
+        /// Whether the token can end an operand, so a `-` right after it is a subtraction
+        fn ends_operand(&self) -> bool {
+            matches!(
+                self,
+                Self::Identifier(_)
+                    | Self::Keyword(Keyword::True | Keyword::False)
+                    | Self::Number(_)
+                    | Self::Integer(_)
+                    | Self::Float(_)
+                    | Self::String(_)
+                    | Self::Template(_)
+                    | Self::Punctuation(Punctuation::CloseParen | Punctuation::CloseBracket)
+            )
+        }
+    }
+
+    impl Display for Token {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Identifier(name) => f.write_str(name),
+                Self::Keyword(keyword) => f.write_str(keyword.as_str()),
+                Self::Number(number) => write!(f, "{number}"),
+                Self::Integer(number) => write!(f, "{number}"),
+                Self::Float(number) => write!(f, "{number}"),
+                Self::String(string) => write!(f, "\"{string}\""),
+                Self::Template(parts) => {
+                    f.write_str("\"")?;
+                    for part in parts {
+                        match part {
+                            TemplatePart::Text(text) => f.write_str(text)?,
+                            TemplatePart::Expression { code, .. } => write!(f, "${{{code}}}")?,
+                        }
+                    }
+                    f.write_str("\"")
+                }
+                Self::Punctuation(punctuation) => write!(f, "{}", punctuation.as_char()),
+                Self::Operator(operator) => f.write_str(operator.as_str()),
+                Self::Comment(CommentKind::Line, text) => write!(f, "//{text}"),
+                Self::Comment(CommentKind::Block, text) => write!(f, "/*{text}*/"),
+            }
+        }
+    }
+
+    /// A [`Token`] along with its location in the code
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct SpannedToken {
+        pub token: Token,
+        pub span: Span,
+    }
+
+    /// [`Lexer`] transforms the given code into a stream of [`Token`]s.
+    #[derive(Clone)]
+    pub struct Lexer<'a> {
+        code: &'a str,
+        file: FileId,
+        chars: Peekable<CharIndices<'a>>,
+        /// End of the last token if it can end an operand
+        operand_end: Option<usize>,
+        /// Templates whose `${}` is being lexed
+        template_depth: usize,
+    }
+
+    impl<'a> Lexer<'a> {
+        /// Create a [`Lexer`] given some code and the [`FileId`] its spans will refer to
+        pub fn new(code: &'a str, file: FileId) -> Self {
+            Self {
+                code,
+                file,
+                chars: code.char_indices().peekable(),
+                operand_end: None,
+                template_depth: 0,
+            }
+        }
+
+        /// Create a [`Lexer`] that starts at the byte `offset` of the code
+        pub fn with_offset(code: &'a str, file: FileId, offset: usize) -> Self {
+            let mut lexer = Self::new(code, file);
+            while lexer.chars.next_if(|(i, _)| *i < offset).is_some() {}
+            lexer
+        }
+
+        /// Byte offset of the next character
+        fn offset(&mut self) -> usize {
+            self.chars
+                .peek()
+                .map(|(i, _)| *i)
+                .unwrap_or(self.code.len())
+        }
+
+        fn skip_while(&mut self, predicate: impl Fn(char) -> bool) {
+            while self.chars.next_if(|(_, c)| predicate(*c)).is_some() {}
+        }
+
+        /// Consume the next character if it's `expected`
+        fn eat(&mut self, expected: char) -> bool {
+            self.chars.next_if(|(_, c)| *c == expected).is_some()
+        }
+
+        fn error(&self, kind: ParseErrorKind, offset: usize) -> ParseError {
+            ParseError::new(kind, offset, self.code)
+        }
+
+        /// Decode the content of a string literal whose opening quote is at `start`.
+        ///
+        /// Literals embedding `${expression}`s are [`Token::Template`]s, the others [`Token::String`]s.
+        /// Invalid escape sequences are reported once the whole literal is consumed.
+        fn string_literal(&mut self, start: usize) -> Result<Token, ParseError> {
+            let mut parts = Vec::new();
+            let mut value = String::new();
+            let mut invalid_escape = None;
+
+            loop {
+                let Some((offset, c)) = self.chars.next() else {
+                    return Err(self.error(ParseErrorKind::UnterminatedString, start));
+                };
+
+                match c {
+                    '"' => break,
+                    '\\' => match self.escape_sequence() {
+                        Some(c) => value.push(c),
+                        None => {
+                            invalid_escape.get_or_insert(offset);
+                        }
+                    },
+                    '$' if self.eat('{') => {
+                        if !value.is_empty() {
+                            parts.push(TemplatePart::Text(std::mem::take(&mut value)));
+                        }
+                        let code = self.embedded_expression(start)?;
+                        parts.push(TemplatePart::Expression {
+                            code: code.to_owned(),
+                            offset: offset + 2,
+                        });
+                    }
+                    c => value.push(c),
+                }
+            }
+
+            if let Some(offset) = invalid_escape {
+                return Err(self.error(ParseErrorKind::InvalidEscape, offset));
+            }
+            if parts.is_empty() {
+                return Ok(Token::String(value));
+            }
+            if !value.is_empty() {
+                parts.push(TemplatePart::Text(value));
+            }
+            Ok(Token::Template(parts))
+        }
+
+        /// Consume the code of a `${}` up to its closing brace, inside the string literal
+        /// whose opening quote is at `start`
+        fn embedded_expression(&mut self, start: usize) -> Result<&'a str, ParseError> {
+            if self.template_depth == MAX_NESTING_DEPTH {
+                return Err(self.error(ParseErrorKind::NestingTooDeep, start));
+            }
+
+            let code_start = self.offset();
+            let mut tokens = self.clone();
+            tokens.operand_end = None;
+            tokens.template_depth += 1;
+
+            let mut depth = 0;
+            let code_end = loop {
+                let Some(token) = tokens.next() else {
+                    return Err(self.error(ParseErrorKind::UnterminatedString, start));
+                };
+                let token = token?;
+                match token.token {
+                    Token::Punctuation(Punctuation::OpenBrace) => depth += 1,
+                    Token::Punctuation(Punctuation::CloseBrace) if depth == 0 => {
+                        break token.span.start;
+                    }
+                    Token::Punctuation(Punctuation::CloseBrace) => depth -= 1,
+                    _ => {}
+                }
+            };
+
+            while self.chars.next_if(|(i, _)| *i <= code_end).is_some() {}
+            Ok(&self.code[code_start..code_end])
+        }
+
+        /// Scan a number literal starting at `start`, such as `3`, `-3`, `2.5` or `1e-3`.
+        ///
+        /// Non-negative integers are [`Token::Number`]s, negative ones [`Token::Integer`]s,
+        /// and anything with a fraction or an exponent a [`Token::Float`].
+        fn number_literal(&mut self, start: usize) -> Result<Token, ParseError> {
+            let bytes = self.code.as_bytes();
+            let digits_end = |mut i: usize| {
+                while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+                    i += 1;
+                }
+                i
+            };
+
+            let is_negative = bytes[start] == b'-';
+            let mut end = digits_end(if is_negative { start + 1 } else { start });
+            let mut is_float = false;
+
+            // Fraction
+            if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) {
+                is_float = true;
+                end = digits_end(end + 1);
+            }
+
+            // Exponent
+            if matches!(bytes.get(end), Some(b'e' | b'E')) {
+                let mut exponent = end + 1;
+                if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+                    exponent += 1;
+                }
+                if bytes.get(exponent).is_some_and(u8::is_ascii_digit) {
+                    is_float = true;
+                    end = digits_end(exponent);
+                }
+            }
+
+            while self.chars.next_if(|(i, _)| *i < end).is_some() {}
+
+            let text = &self.code[start..end];
+            let token = if is_float {
+                text.parse().map(Token::Float).ok()
+            } else if is_negative {
+                text.parse().map(Token::Integer).ok()
+            } else {
+                text.parse().map(Token::Number).ok()
+            };
+            token.ok_or_else(|| self.error(ParseErrorKind::InvalidNumber, start))
+        }
+
+        /// Decode the escape sequence that follows a `\`
+        fn escape_sequence(&mut self) -> Option<char> {
+            let (_, c) = self.chars.next()?;
+            match c {
+                'n' => Some('\n'),
+                't' => Some('\t'),
+                'r' => Some('\r'),
+                '0' => Some('\0'),
+                '\\' => Some('\\'),
+                '"' => Some('"'),
+                '$' => Some('$'),
+                '\'' => Some('\''),
+                'u' => {
+                    self.chars.next_if(|(_, c)| *c == '{')?;
+                    let mut code_point = String::new();
+                    while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+                        code_point.push(c);
+                    }
+                    self.chars.next_if(|(_, c)| *c == '}')?;
+                    if code_point.is_empty() || code_point.len() > 6 {
+                        return None;
+                    }
+                    u32::from_str_radix(&code_point, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    impl Iterator for Lexer<'_> {
+        type Item = Result<SpannedToken, ParseError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.skip_while(char::is_whitespace);
+
+            let (start, c) = self.chars.next()?;
+
+            let token = match c {
+                '(' => Token::Punctuation(Punctuation::OpenParen),
+                ')' => Token::Punctuation(Punctuation::CloseParen),
+                '{' => Token::Punctuation(Punctuation::OpenBrace),
+                '}' => Token::Punctuation(Punctuation::CloseBrace),
+                '[' => Token::Punctuation(Punctuation::OpenBracket),
+                ']' => Token::Punctuation(Punctuation::CloseBracket),
+                ';' => Token::Punctuation(Punctuation::Semicolon),
+                ',' => Token::Punctuation(Punctuation::Comma),
+                '.' => Token::Punctuation(Punctuation::Dot),
+                ':' => Token::Punctuation(Punctuation::Colon),
+                '=' if self.eat('=') => Token::Operator(Operator::EqualEqual),
+                '=' if self.eat('>') => Token::Operator(Operator::FatArrow),
+                '=' => Token::Punctuation(Punctuation::Equal),
+                '/' if self.eat('/') => {
+                    self.skip_while(|c| c != '\n');
+                    let end = self.offset();
+                    Token::Comment(CommentKind::Line, self.code[start + 2..end].to_owned())
+                }
+                '/' if self.eat('*') => {
+                    let text_start = start + 2;
+                    let Some(text_len) = self.code[text_start..].find("*/") else {
+                        self.skip_while(|_| true);
+                        return Some(Err(self.error(ParseErrorKind::UnterminatedComment, start)));
+                    };
+                    let text_end = text_start + text_len;
+                    while self.chars.next_if(|(i, _)| *i < text_end + 2).is_some() {}
+                    Token::Comment(
+                        CommentKind::Block,
+                        self.code[text_start..text_end].to_owned(),
+                    )
+                }
+                '"' => match self.string_literal(start) {
+                    Ok(token) => token,
+                    Err(err) => return Some(Err(err)),
+                },
+                c if c.is_ascii_digit() => match self.number_literal(start) {
+                    Ok(token) => token,
+                    Err(err) => return Some(Err(err)),
+                },
+                '-' if self.eat('=') => Token::Operator(Operator::MinusEqual),
+                // Negative numbers can't follow an operand, `a-1`, `a - 1` and `a -1` are
+                // subtractions, except where the parser separates values by whitespace
+                '-' if self.operand_end.is_none()
+                    && matches!(self.chars.peek(), Some((_, c)) if c.is_ascii_digit()) =>
+                {
+                    match self.number_literal(start) {
+                        Ok(token) => token,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                '-' if self.operand_end != Some(start)
+                    && matches!(self.chars.peek(), Some((_, c)) if !c.is_whitespace()) =>
+                {
+                    Token::Operator(Operator::Negate)
+                }
+                '/' if self.eat('=') => Token::Operator(Operator::SlashEqual),
+                '/' => Token::Operator(Operator::Slash),
+                '+' if self.eat('=') => Token::Operator(Operator::PlusEqual),
+                '+' => Token::Operator(Operator::Plus),
+                '-' => Token::Operator(Operator::Minus),
+                '*' if self.eat('=') => Token::Operator(Operator::StarEqual),
+                '*' => Token::Operator(Operator::Star),
+                '%' if self.eat('=') => Token::Operator(Operator::PercentEqual),
+                '%' => Token::Operator(Operator::Percent),
+                '!' if self.eat('=') => Token::Operator(Operator::BangEqual),
+                '!' => Token::Operator(Operator::Bang),
+                '<' if self.eat('=') => Token::Operator(Operator::LessEqual),
+                '<' => Token::Operator(Operator::Less),
+                '>' if self.eat('=') => Token::Operator(Operator::GreaterEqual),
+                '>' => Token::Operator(Operator::Greater),
+                '&' if self.eat('&') => Token::Operator(Operator::AmpAmp),
+                '|' if self.eat('|') => Token::Operator(Operator::PipePipe),
+                '?' => Token::Operator(Operator::Question),
+                c if c.is_alphabetic() || c == '_' => {
+                    self.skip_while(|c| c.is_alphanumeric() || c == '_');
+                    let word = &self.code[start..self.offset()];
+                    match Keyword::from_word(word) {
+                        Some(keyword) => Token::Keyword(keyword),
+                        None => Token::Identifier(word.to_owned()),
+                    }
+                }
+                c => {
+                    return Some(Err(
+                        self.error(ParseErrorKind::UnexpectedToken(c.to_string()), start)
+                    ));
+                }
+            };
+
+            let span = Span::new(self.file, start, self.offset());
+            self.operand_end = token.ends_operand().then_some(span.end);
+            Some(Ok(SpannedToken { token, span }))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn tokens_with_their_spans() {
+            let tokens = Lexer::new("let s = print(\"hi\" 12);", 3)
+                .map(|token| {
+                    let SpannedToken { token, span } = token.unwrap();
+                    assert_eq!(span.file, 3);
+                    (token, span.start..span.end)
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                tokens,
+                [
+                    (Token::Keyword(Keyword::Let), 0..3),
+                    (Token::Identifier("s".to_owned()), 4..5),
+                    (Token::Punctuation(Punctuation::Equal), 6..7),
+                    (Token::Identifier("print".to_owned()), 8..13),
+                    (Token::Punctuation(Punctuation::OpenParen), 13..14),
+                    (Token::String("hi".to_owned()), 14..18),
+                    (Token::Number(12), 19..21),
+                    (Token::Punctuation(Punctuation::CloseParen), 21..22),
+                    (Token::Punctuation(Punctuation::Semicolon), 22..23),
+                ]
+            );
+        }
+
+        #[test]
+        fn escape_sequences() {
+            let cases = [
+                (r#""a\"b\\c""#, Ok("a\"b\\c")),
+                (r#""\n\t\r\0\'""#, Ok("\n\t\r\0'")),
+                (r#""\u{e9}t\u{1F600}""#, Ok("ét😀")),
+                (r#""¿qué?""#, Ok("¿qué?")),
+                (r#""ok\q\u{110000}""#, Err(3)),
+                (r#""\u{}""#, Err(1)),
+                (r#""\u{e9""#, Err(1)),
+            ];
+
+            for (code, expected) in cases {
+                let token = Lexer::new(code, 0).next().unwrap();
+                let token = token
+                    .map(|token| token.token)
+                    .map_err(|err| (err.kind, err.offset));
+                let expected = expected
+                    .map(|value| Token::String(value.to_owned()))
+                    .map_err(|offset| (ParseErrorKind::InvalidEscape, offset));
+                assert_eq!(token, expected, "{code}");
+            }
+        }
+
+        #[test]
+        fn number_literals() {
+            let cases = [
+                ("42", Token::Number(42)),
+                ("-42", Token::Integer(-42)),
+                ("2.5", Token::Float(2.5)),
+                ("-0.5", Token::Float(-0.5)),
+                ("1e3", Token::Float(1000.0)),
+                ("2.5E-1", Token::Float(0.25)),
+                ("-1e+2", Token::Float(-100.0)),
+            ];
+
+            for (code, expected) in cases {
+                let token = Lexer::new(code, 0).next().unwrap().unwrap();
+                assert_eq!(token.token, expected, "{code}");
+                assert_eq!(token.span.end, code.len(), "{code}");
+            }
+
+            // A dot without digits after it is not part of the number
+            let tokens = Lexer::new("1.x", 0)
+                .map(|token| token.unwrap().token)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                tokens,
+                [
+                    Token::Number(1),
+                    Token::Punctuation(Punctuation::Dot),
+                    Token::Identifier("x".to_owned()),
+                ]
+            );
+        }
+    }
+}
+
 pub mod parser {
-    use std::{iter::Peekable, str::Chars};
+    use std::fmt::Display;
+
+    use crate::lexer::{Keyword, Lexer, Operator, Punctuation, SpannedToken, TemplatePart, Token};
 
     pub use slab::Slab;
 
     pub type ParserObjectKey = usize;
 
+    /// Identifies each piece of code given to the [`Parser`]
+    pub type FileId = usize;
+
+    /// Location of a [`ParserObject`] in the parsed code
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Span {
+        pub file: FileId,
+        /// Byte offset where the object starts
+        pub start: usize,
+        /// Byte offset where the object ends, exclusive
+        pub end: usize,
+    }
+
+    impl Span {
+        pub fn new(file: FileId, start: usize, end: usize) -> Self {
+            Self { file, start, end }
+        }
+    }
+
     /// [`Parser`] transforms the given code into an AST.
     #[derive(Debug, Clone)]
     pub struct Parser {
         objects: Slab<ParserObject>,
+        spans: Slab<Span>,
+        sources: Vec<String>,
+        /// Comments are kept apart from the objects
+        comments: Vec<SpannedToken>,
         global_block: ParserObjectKey,
     }
 
@@ -21,24 +721,91 @@ pub mod parser {
             block_value: ParserObjectKey,
             var_name: String,
         },
+        /// `struct Name { field field }`
+        StructDef {
+            struct_name: String,
+            fields: Vec<String>,
+        },
+        /// `enum Name { Variant(field) Variant }`
+        EnumDef {
+            enum_name: String,
+            /// Name and payload fields of each variant
+            variants: Vec<(String, Vec<String>)>,
+        },
         FnDef {
+            /// Name of a function declaration, anonymous functions don't have it
+            fn_name: Option<String>,
             arguments_block: ParserObjectKey,
             block_value: ParserObjectKey,
-            capture_value: ParserObjectKey,
+            /// Functions without a capture area see the variables around them
+            capture_value: Option<ParserObjectKey>,
         },
         IfDef {
             condition_block: ParserObjectKey,
             block_value: ParserObjectKey,
+            /// Body of the `else` branch, or the [`ParserObject::IfDef`] of an `else if`
+            else_block: Option<ParserObjectKey>,
+        },
+        /// `try { body } catch (error) { body }`
+        TryDef {
+            block_value: ParserObjectKey,
+            /// Variable holding the caught error
+            var_name: String,
+            catch_block: ParserObjectKey,
+        },
+        /// `match value { pattern => value }`
+        MatchDef {
+            value: ParserObjectKey,
+            arms: Vec<MatchArm>,
+        },
+        WhileDef {
+            condition_block: ParserObjectKey,
+            block_value: ParserObjectKey,
+        },
+        ForDef {
+            var_name: String,
+            iterable: ParserObjectKey,
+            block_value: ParserObjectKey,
+        },
+        Break {
+            /// Value the loop evaluates to
+            value: Option<ParserObjectKey>,
+        },
+        Continue,
+        Return {
+            /// Value the function returns
+            value: Option<ParserObjectKey>,
         },
         NumberVal {
             value: usize,
         },
+        IntegerVal {
+            value: i64,
+        },
+        FloatVal {
+            value: f64,
+        },
         StringVal {
             value: String,
         },
         BytesVal {
             value: Vec<u8>,
         },
+        /// `"text ${expression}"`
+        TemplateVal {
+            segments: Vec<TemplateSegment>,
+        },
+        BoolVal {
+            value: bool,
+        },
+        /// `[item item]`
+        ListVal {
+            items: ParserObjectKey,
+        },
+        /// `{ "key": value }`
+        MapVal {
+            entries: Vec<(String, ParserObjectKey)>,
+        },
         FunctionCall {
             fn_name: String,
             arguments: ParserObjectKey,
@@ -49,6 +816,134 @@ pub mod parser {
         PropertyRef {
             path: Vec<String>,
         },
+        BinaryOp {
+            operator: BinaryOperator,
+            left: ParserObjectKey,
+            right: ParserObjectKey,
+        },
+        UnaryOp {
+            operator: UnaryOperator,
+            value: ParserObjectKey,
+        },
+        /// `value[index]`
+        Index {
+            value: ParserObjectKey,
+            index: ParserObjectKey,
+        },
+        /// `value[start:end]`, both bounds are optional
+        Slice {
+            value: ParserObjectKey,
+            start: Option<ParserObjectKey>,
+            end: Option<ParserObjectKey>,
+        },
+        /// `target = value`, or `target += value` and such when there is an `operator`
+        Assignment {
+            target: ParserObjectKey,
+            operator: Option<BinaryOperator>,
+            value: ParserObjectKey,
+        },
+        /// `value?`, returns early from the function on an `Err` or `None`
+        Propagate {
+            value: ParserObjectKey,
+        },
+    }
+
+    /// Arm of a [`ParserObject::MatchDef`]
+    #[derive(Debug, Clone)]
+    pub struct MatchArm {
+        pub pattern: MatchPattern,
+        pub block_value: ParserObjectKey,
+    }
+
+    /// Values accepted by a [`MatchArm`]
+    #[derive(Debug, Clone)]
+    pub enum MatchPattern {
+        /// `_`, anything
+        Wildcard,
+        /// `Variant(binding binding)`, enums with that variant, binding their payload
+        Variant {
+            variant_name: String,
+            bindings: Vec<String>,
+        },
+        /// Values equal to a literal
+        Literal(ParserObjectKey),
+    }
+
+    /// Piece of a [`ParserObject::TemplateVal`]
+    #[derive(Debug, Clone)]
+    pub enum TemplateSegment {
+        Text(String),
+        Expression(ParserObjectKey),
+    }
+
+    /// Operator between two expressions, such as `a + b`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BinaryOperator {
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Rem,
+        Equal,
+        NotEqual,
+        Less,
+        LessEqual,
+        Greater,
+        GreaterEqual,
+        And,
+        Or,
+    }
+
+    impl BinaryOperator {
+        fn from_operator(operator: Operator) -> Option<Self> {
+            match operator {
+                Operator::Plus => Some(Self::Add),
+                Operator::Minus => Some(Self::Sub),
+                Operator::Star => Some(Self::Mul),
+                Operator::Slash => Some(Self::Div),
+                Operator::Percent => Some(Self::Rem),
+                Operator::EqualEqual => Some(Self::Equal),
+                Operator::BangEqual => Some(Self::NotEqual),
+                Operator::Less => Some(Self::Less),
+                Operator::LessEqual => Some(Self::LessEqual),
+                Operator::Greater => Some(Self::Greater),
+                Operator::GreaterEqual => Some(Self::GreaterEqual),
+                Operator::AmpAmp => Some(Self::And),
+                Operator::PipePipe => Some(Self::Or),
+                _ => None,
+            }
+        }
+
+        /// Operator applied by a compound assignment, such as `+=`
+        fn from_compound_assignment(operator: Operator) -> Option<Self> {
+            match operator {
+                Operator::PlusEqual => Some(Self::Add),
+                Operator::MinusEqual => Some(Self::Sub),
+                Operator::StarEqual => Some(Self::Mul),
+                Operator::SlashEqual => Some(Self::Div),
+                Operator::PercentEqual => Some(Self::Rem),
+                _ => None,
+            }
+        }
+
+        /// Operators with a higher precedence bind tighter
+        pub fn precedence(&self) -> u8 {
+            match self {
+                Self::Or => 1,
+                Self::And => 2,
+                Self::Equal | Self::NotEqual => 3,
+                Self::Less | Self::LessEqual | Self::Greater | Self::GreaterEqual => 4,
+                Self::Add | Self::Sub => 5,
+                Self::Mul | Self::Div | Self::Rem => 6,
+            }
+        }
+    }
+
+    /// Operator before an expression, such as `!a`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UnaryOperator {
+        Not,
+        Neg,
     }
 
     impl ParserObject {
@@ -68,65 +963,108 @@ pub mod parser {
         }
     }
 
-    #[inline(always)]
-    fn slice_with_size(start: usize, end: usize, code: &str) -> Option<&str> {
-        if code.len() < end {
-            None
-        } else {
-            Some(&code[start..end])
-        }
+    /// Resolve the line and column, starting from 1, of a byte offset
+    fn line_column(code: &str, offset: usize) -> (usize, usize) {
+        let before = &code[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
     }
 
-    #[inline(always)]
-    fn slice_until(until: char, chars: &mut Peekable<Chars>) -> String {
-        chars.take_while(|&v| v != until).collect::<String>()
+    /// Levels that expressions and blocks can be nested, so that parsing and evaluating
+    /// them doesn't overflow the stack
+    pub const MAX_NESTING_DEPTH: usize = 256;
+
+    /// Kind of syntax error found by the [`Parser`]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ParseErrorKind {
+        /// A token that is not valid in its position
+        UnexpectedToken(String),
+        /// The code ended in the middle of an expression
+        UnexpectedEnd,
+        /// A string literal that is never closed
+        UnterminatedString,
+        /// An unknown or malformed escape sequence in a string literal
+        InvalidEscape,
+        /// A block comment that is never closed
+        UnterminatedComment,
+        /// A number literal that can't be represented
+        InvalidNumber,
+        /// A delimiter that is never closed, or that closes nothing
+        UnbalancedDelimiter(char),
+        /// An operator used where it is not allowed
+        MisplacedOperator(String),
+        /// A keyword used where it is not allowed, such as `break` outside of a loop
+        MisplacedKeyword(String),
+        /// A name given twice where names must be unique, such as the fields of a struct
+        DuplicateName(String),
+        /// Expressions nested deeper than [`MAX_NESTING_DEPTH`]
+        NestingTooDeep,
     }
 
-    #[inline(always)]
-    fn slice_until_delimeter(chars: &mut Peekable<Chars>) -> String {
-        let until = [',', ';', ')', '}', ' ', '\n', ']', '\r'];
-        let mut s = String::new();
-        while let Some(c) = chars.next_if(|v| !until.contains(v)) {
-            s.push_str(&c.to_string());
+    impl Display for ParseErrorKind {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::UnexpectedToken(token) => write!(f, "unexpected token `{token}`"),
+                Self::UnexpectedEnd => f.write_str("unexpected end of code"),
+                Self::UnterminatedString => f.write_str("unterminated string"),
+                Self::InvalidEscape => f.write_str("invalid escape sequence"),
+                Self::UnterminatedComment => f.write_str("unterminated comment"),
+                Self::InvalidNumber => f.write_str("invalid number"),
+                Self::UnbalancedDelimiter(c) => write!(f, "unbalanced delimiter `{c}`"),
+                Self::MisplacedOperator(operator) => write!(f, "misplaced operator `{operator}`"),
+                Self::MisplacedKeyword(keyword) => write!(f, "misplaced keyword `{keyword}`"),
+                Self::DuplicateName(name) => write!(f, "duplicate name `{name}`"),
+                Self::NestingTooDeep => {
+                    write!(f, "nesting deeper than {MAX_NESTING_DEPTH} levels")
+                }
+            }
         }
-        s
     }
 
-    #[inline(always)]
-    fn count_unexpected_between(start: usize, until: char, code: &str) -> usize {
-        let code = &code[start..];
-        code.chars()
-            .take_while(|&v| v != until)
-            .filter(|v| v.is_whitespace() || v == &';' || v == &')')
-            .count()
+    /// Syntax error found by the [`Parser`]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParseError {
+        pub kind: ParseErrorKind,
+        /// Byte offset in the parsed code
+        pub offset: usize,
+        /// Line number, starting from 1
+        pub line: usize,
+        /// Column number in characters, starting from 1
+        pub column: usize,
     }
 
-    enum PerfomedAction {
-        EnteredGlobalScope,
-        DefinedVariable,
-        OpenedBlock,
-        ClosedBlock,
-        ClosedStatement,
-        OpenedString,
-        ClosedString,
-        FoundOperator(char),
-        CalledFunction,
-        ReferencedVariable,
-        FoundNumber,
+    impl ParseError {
+        pub(crate) fn new(kind: ParseErrorKind, offset: usize, code: &str) -> Self {
+            let (line, column) = line_column(code, offset);
+
+            Self {
+                kind,
+                offset,
+                line,
+                column,
+            }
+        }
     }
 
-    #[derive(Clone, Copy, PartialEq)]
-    enum BlockType {
-        Generic,
-        FuncCall,
-        Value,
-        FuncValue,
-        FuncCapture,
+    impl Display for ParseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "Syntax error: {} at line {}, column {}",
+                self.kind, self.line, self.column
+            )
+        }
     }
 
+    impl std::error::Error for ParseError {}
+
+    pub type ParseResult<T> = Result<T, Vec<ParseError>>;
+
     impl Parser {
         /// Create a [`Parser`] given some code
-        pub fn new(code: &str) -> Self {
+        pub fn new(code: &str) -> ParseResult<Self> {
             let mut parser = Slab::new();
 
             let global_block_object = ParserObject::Block {
@@ -134,1475 +1072,6826 @@ pub mod parser {
             };
             let global_block = parser.insert(global_block_object);
 
+            let mut spans = Slab::new();
+            spans.insert(Span::new(0, 0, code.len()));
+
             let mut parser = Self {
                 objects: parser,
+                spans,
+                sources: Vec::new(),
+                comments: Vec::new(),
                 global_block,
             };
 
-            parser.parse(code);
+            parser.parse(code)?;
 
-            parser
+            Ok(parser)
         }
 
-        /// Parse additional code
-        pub fn parse(&mut self, code: &str) {
-            let parser = &mut self.objects;
-            let global_block = self.global_block;
-
-            let mut block_indexes = vec![(global_block, BlockType::Generic)];
-            let mut string_count = 0;
-            let mut last_action = PerfomedAction::EnteredGlobalScope;
+        /// Parse additional code, which is identified by a new [`FileId`].
+        ///
+        /// If the code contains syntax errors nothing gets added to the [`Parser`].
+        pub fn parse(&mut self, code: &str) -> ParseResult<()> {
+            let first_new_key = self.objects.len();
+            let comments_len = self.comments.len();
+            let global_block_len = self
+                .objects
+                .get(self.global_block)
+                .and_then(|global_block| global_block.objects())
+                .map(|objects| objects.len())
+                .unwrap_or_default();
+
+            let file = self.sources.len();
+            self.sources.push(code.to_owned());
+
+            let errors = self.parse_code(code, file);
+
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                self.sources.pop();
+                self.comments.truncate(comments_len);
+                self.rollback(first_new_key, global_block_len);
+                Err(errors)
+            }
+        }
 
-            let len = code.len();
-            let mut chars = code.chars().peekable();
+        /// Remove every object added since `first_new_key`
+        fn rollback(&mut self, first_new_key: usize, global_block_len: usize) {
+            let new_keys = self
+                .objects
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|key| *key >= first_new_key)
+                .collect::<Vec<ParserObjectKey>>();
+
+            // Removing in reverse order keeps the keys sequential for the next insertions
+            for key in new_keys.into_iter().rev() {
+                self.objects.remove(key);
+                self.spans.remove(key);
+            }
 
-            fn advance_by(how_much: usize, chars: &mut Peekable<Chars>) {
-                for _ in 0..how_much {
-                    chars.next();
-                }
+            if let Some(ParserObject::Block { objects }) = self.objects.get_mut(self.global_block) {
+                objects.truncate(global_block_len);
             }
+        }
 
-            loop {
-                let i = len - chars.size_hint().1.unwrap();
+        fn parse_code(&mut self, code: &str, file: FileId) -> Vec<ParseError> {
+            let mut token_parser = TokenParser {
+                objects: &mut self.objects,
+                spans: &mut self.spans,
+                comments: &mut self.comments,
+                lexer: Lexer::new(code, file),
+                peeked: None,
+                last_end: 0,
+                loop_depth: 0,
+                in_function: false,
+                separated_values: None,
+                nesting: 0,
+                declared_fns: Vec::new(),
+                code,
+                file,
+                errors: Vec::new(),
+            };
+            token_parser.parse_objects(self.global_block, None);
 
-                let val = chars.next();
+            let mut errors = token_parser.errors;
+            errors.sort_by_key(|err| err.offset);
+            errors.dedup();
+            errors
+        }
 
-                if val.is_none() {
-                    break;
-                }
+        /// Retrieve the global block object
+        pub fn get_global(&self) -> ParserObjectKey {
+            self.global_block
+        }
 
-                // Skip spaces and line breaks
-                if string_count == 0 && (val == Some(' ') || val == Some('\n')) || val == Some('\r')
-                {
-                    continue;
-                }
+        /// Retrieve a ParserObject given a `key`
+        #[inline(always)]
+        pub fn get_object(&self, key: ParserObjectKey) -> Option<&ParserObject> {
+            self.objects.get(key)
+        }
 
-                let val = val.unwrap();
+        /// Retrieve the [`Span`] of a ParserObject given a `key`
+        #[inline(always)]
+        pub fn get_span(&self, key: ParserObjectKey) -> Option<Span> {
+            self.spans.get(key).copied()
+        }
 
-                let (current_block, current_block_type) = *block_indexes.last().unwrap();
+        /// Retrieve every comment found in the parsed code
+        pub fn get_comments(&self) -> &[SpannedToken] {
+            &self.comments
+        }
 
-                if val == ')' && string_count == 0 {
-                    block_indexes.pop();
-                    last_action = PerfomedAction::ClosedStatement;
-                    continue;
-                }
+        /// Retrieve the code identified by a [`FileId`]
+        pub fn get_source(&self, file: FileId) -> Option<&str> {
+            self.sources.get(file).map(|source| source.as_str())
+        }
 
-                // Check operator syntax
-                if val == '=' && string_count == 0 {
-                    if matches!(last_action, PerfomedAction::DefinedVariable) {
-                        last_action = PerfomedAction::FoundOperator('=');
-                    } else {
-                        panic!("Syntax error: Operator '=' is used to define initial values to variables.")
-                    }
-                    continue;
-                }
-
-                // End a statement
-                if val == ';' && string_count == 0 {
-                    if BlockType::Value == current_block_type {
-                        block_indexes.pop();
-                    }
-
-                    last_action = PerfomedAction::ClosedStatement;
-                    continue;
-                }
-
-                if val == '"' {
-                    // String closed
-                    if string_count > 0 {
-                        let string_val = ParserObject::BytesVal {
-                            value: code[i - string_count + 1..i]
-                                .chars()
-                                .collect::<String>()
-                                .as_bytes()
-                                .to_vec(),
-                        };
+        /// Resolve the line and column, starting from 1, where a [`Span`] starts
+        pub fn get_location(&self, span: Span) -> Option<(usize, usize)> {
+            let source = self.get_source(span.file)?;
+            source
+                .get(..span.start)
+                .map(|_| line_column(source, span.start))
+        }
+    }
 
-                        let string_key = parser.insert(string_val);
+    /// Builds [`ParserObject`]s out of a stream of [`SpannedToken`]s
+    struct TokenParser<'a> {
+        objects: &'a mut Slab<ParserObject>,
+        spans: &'a mut Slab<Span>,
+        comments: &'a mut Vec<SpannedToken>,
+        lexer: Lexer<'a>,
+        /// Token peeked but not consumed yet
+        peeked: Option<SpannedToken>,
+        /// End of the last consumed token
+        last_end: usize,
+        /// Loops around the current position, up to the closest function
+        loop_depth: usize,
+        /// Whether the current position is inside a function
+        in_function: bool,
+        /// Nesting of the values separated by whitespace around the current position,
+        /// such as call arguments, where `a -1` are two values
+        separated_values: Option<usize>,
+        /// Expressions around the current position
+        nesting: usize,
+        /// Names of the functions declared in the current block
+        declared_fns: Vec<String>,
+        code: &'a str,
+        file: FileId,
+        errors: Vec<ParseError>,
+    }
 
-                        let block_value = parser.get_mut(current_block).unwrap();
-                        if let ParserObject::Block { objects } = block_value {
-                            objects.push(string_key);
+    impl TokenParser<'_> {
+        fn peek(&mut self) -> Option<&SpannedToken> {
+            if self.peeked.is_none() {
+                // Lexing errors are collected and the invalid tokens skipped
+                for token in self.lexer.by_ref() {
+                    match token {
+                        Ok(token) if token.token.is_trivia() => self.comments.push(token),
+                        Ok(token) => {
+                            self.peeked = Some(token);
+                            break;
                         }
-                        last_action = PerfomedAction::ClosedString;
-                        string_count = 0
-                    } else {
-                        last_action = PerfomedAction::OpenedString;
-                        string_count += 1;
+                        Err(err) => self.errors.push(err),
                     }
-                    continue;
                 }
+            }
+            self.peeked.as_ref()
+        }
 
-                // Start a block
-                if val == '{' && string_count == 0 {
-                    let block = ParserObject::Block {
-                        objects: Vec::new(),
-                    };
-                    let block_key = parser.insert(block);
+        fn peek_token(&mut self) -> Option<&Token> {
+            self.peek().map(|token| &token.token)
+        }
 
-                    block_indexes.push((block_key, BlockType::Generic));
-                    let current_block = parser.get_mut(current_block).unwrap();
-                    current_block.add_object(block_key);
+        /// Peek the token `n` positions after the next one, without consuming anything
+        fn peek_nth_token(&mut self, n: usize) -> Option<Token> {
+            if n == 0 {
+                return self.peek_token().cloned();
+            }
+            self.peek();
+            self.lexer
+                .clone()
+                .filter_map(Result::ok)
+                .filter(|token| !token.token.is_trivia())
+                .nth(n - 1)
+                .map(|token| token.token)
+        }
 
-                    last_action = PerfomedAction::OpenedBlock;
+        fn next_token(&mut self) -> Option<SpannedToken> {
+            self.peek();
+            let token = self.peeked.take();
+            if let Some(token) = &token {
+                self.last_end = token.span.end;
+            }
+            token
+        }
 
-                    continue;
-                }
+        fn advance(&mut self) {
+            self.next_token();
+        }
 
-                // Closing a block
-                if val == '}' && string_count == 0 {
-                    block_indexes.pop();
-                    if let Some((_, BlockType::FuncValue)) = block_indexes.last() {
-                        block_indexes.pop();
-                    }
-                    last_action = PerfomedAction::ClosedBlock;
-                    continue;
-                }
+        /// Whether the next token is `punctuation`
+        fn at(&mut self, punctuation: Punctuation) -> bool {
+            self.peek_token() == Some(&Token::Punctuation(punctuation))
+        }
 
-                if val == '[' && string_count == 0 {
-                    continue;
-                }
+        /// Whether the next token is `punctuation` and comes right after the previous token
+        fn at_adjacent(&mut self, punctuation: Punctuation) -> bool {
+            let last_end = self.last_end;
+            self.at(punctuation) && self.peek().map(|token| token.span.start) == Some(last_end)
+        }
 
-                // Closing a capture area block
-                if val == ']' && string_count == 0 {
-                    block_indexes.pop();
-                    last_action = PerfomedAction::ClosedBlock;
-                    continue;
-                }
+        fn error(&self, kind: ParseErrorKind, offset: usize) -> ParseError {
+            ParseError::new(kind, offset, self.code)
+        }
 
-                // Variable declarations
-                if string_count == 0 && slice_with_size(i, i + 3, code) == Some("let") {
-                    advance_by(3, &mut chars);
-                    let var_name = slice_until(' ', &mut chars);
-                    let value_block = ParserObject::Block {
-                        objects: Vec::new(),
-                    };
-                    let block_key = parser.insert(value_block);
+        /// Error for the next token, which is not the expected one
+        fn unexpected(&mut self) -> ParseError {
+            self.peek();
+            match &self.peeked {
+                Some(SpannedToken {
+                    token: Token::Punctuation(Punctuation::Equal),
+                    span,
+                }) => self.error(
+                    ParseErrorKind::MisplacedOperator("=".to_owned()),
+                    span.start,
+                ),
+                Some(SpannedToken {
+                    token: Token::Operator(operator),
+                    span,
+                }) => self.error(
+                    ParseErrorKind::MisplacedOperator(operator.as_str().to_owned()),
+                    span.start,
+                ),
+                Some(SpannedToken {
+                    token: Token::Punctuation(punctuation),
+                    span,
+                }) if punctuation.is_closing() => self.error(
+                    ParseErrorKind::UnbalancedDelimiter(punctuation.as_char()),
+                    span.start,
+                ),
+                Some(SpannedToken { span, .. }) => self.error(
+                    ParseErrorKind::UnexpectedToken(self.code[span.start..span.end].to_owned()),
+                    span.start,
+                ),
+                None => self.error(ParseErrorKind::UnexpectedEnd, self.code.len()),
+            }
+        }
 
-                    let var_def = ParserObject::VarDef {
-                        block_value: block_key,
-                        var_name,
-                    };
-                    let var_key = parser.insert(var_def);
+        /// Consume the next token if it's `punctuation`, or fail
+        fn expect(&mut self, punctuation: Punctuation) -> Result<Span, ParseError> {
+            if self.at(punctuation) {
+                Ok(self.next_token().unwrap().span)
+            } else {
+                Err(self.unexpected())
+            }
+        }
 
-                    let current_block = parser.get_mut(current_block).unwrap();
-                    current_block.add_object(var_key);
+        fn insert(&mut self, object: ParserObject, start: usize, end: usize) -> ParserObjectKey {
+            self.spans.insert(Span::new(self.file, start, end));
+            self.objects.insert(object)
+        }
 
-                    block_indexes.push((block_key, BlockType::Value));
+        fn new_block(&mut self, start: usize, end: usize) -> ParserObjectKey {
+            self.insert(
+                ParserObject::Block {
+                    objects: Vec::new(),
+                },
+                start,
+                end,
+            )
+        }
 
-                    last_action = PerfomedAction::DefinedVariable;
+        fn add_object(&mut self, block: ParserObjectKey, object: ParserObjectKey) {
+            self.objects[block].add_object(object);
+        }
 
-                    continue;
-                }
+        fn set_end(&mut self, key: ParserObjectKey, end: usize) {
+            self.spans[key].end = end;
+        }
 
-                if string_count > 0 {
-                    string_count += 1;
-                    continue;
+        /// Skip the tokens of a statement that failed to parse
+        fn synchronize(&mut self) {
+            let mut depth = 0;
+            while let Some(token) = self.peek_token() {
+                match token {
+                    Token::Punctuation(Punctuation::Semicolon) if depth == 0 => {
+                        self.advance();
+                        return;
+                    }
+                    Token::Punctuation(punctuation) if punctuation.closing().is_some() => {
+                        depth += 1;
+                    }
+                    Token::Punctuation(punctuation) if punctuation.is_closing() => {
+                        if depth == 0 {
+                            return;
+                        }
+                        depth -= 1;
+                    }
+                    _ => {}
                 }
+                self.advance();
+            }
+        }
 
-                if string_count == 0 {
-                    // Functions
-                    if count_unexpected_between(i, '(', code) == 0 {
-                        let item_name = slice_until('(', &mut chars);
-                        let item_name = format!("{val}{item_name}");
-
-                        if item_name == "if" {
-                            let expr_block = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let expr_block_key = parser.insert(expr_block);
-
-                            let value_block = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let block_key = parser.insert(value_block);
-
-                            let if_def = ParserObject::IfDef {
-                                block_value: block_key,
-                                condition_block: expr_block_key,
-                            };
-                            let if_key = parser.insert(if_def);
-
-                            let current_block = parser.get_mut(current_block).unwrap();
-                            current_block.add_object(if_key);
-
-                            block_indexes.push((block_key, BlockType::FuncValue));
-                            block_indexes.push((expr_block_key, BlockType::FuncCall));
-
-                            last_action = PerfomedAction::CalledFunction;
-                        } else if item_name == "fn" {
-                            // Function args
-                            let args_block = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let args_block_key = parser.insert(args_block);
-
-                            // Function body
-                            let value_block = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let block_key = parser.insert(value_block);
-
-                            // Function capture area
-                            let value_capture = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let capture_key = parser.insert(value_capture);
-
-                            let fn_def = ParserObject::FnDef {
-                                block_value: block_key,
-                                arguments_block: args_block_key,
-                                capture_value: capture_key,
-                            };
-                            let fn_key = parser.insert(fn_def);
-
-                            let current_block = parser.get_mut(current_block).unwrap();
-                            current_block.add_object(fn_key);
-
-                            block_indexes.push((block_key, BlockType::FuncValue));
-                            block_indexes.push((capture_key, BlockType::FuncCapture));
-                            block_indexes.push((args_block_key, BlockType::FuncCall));
-
-                            last_action = PerfomedAction::CalledFunction;
-                        } else {
-                            let value_block = ParserObject::Block {
-                                objects: Vec::new(),
-                            };
-                            let block_key = parser.insert(value_block);
-
-                            let fn_call_def = ParserObject::FunctionCall {
-                                fn_name: item_name,
-                                arguments: block_key,
-                            };
-                            let fn_call_key = parser.insert(fn_call_def);
-
-                            let current_block = parser.get_mut(current_block).unwrap();
-                            current_block.add_object(fn_call_key);
-
-                            block_indexes.push((block_key, BlockType::FuncCall));
+        /// Parse statements into `block` until the `opener` delimiter gets closed,
+        /// or until there are no tokens left if there is no `opener`.
+        fn parse_objects(&mut self, block: ParserObjectKey, opener: Option<SpannedToken>) {
+            let closing = opener.as_ref().and_then(|opener| match opener.token {
+                Token::Punctuation(punctuation) => punctuation.closing(),
+                _ => None,
+            });
 
-                            last_action = PerfomedAction::CalledFunction;
+            loop {
+                match self.peek_token() {
+                    None => {
+                        if let Some(opener) = &opener {
+                            let delimiter = self.code[opener.span.start..].chars().next();
+                            self.errors.push(self.error(
+                                ParseErrorKind::UnbalancedDelimiter(delimiter.unwrap_or_default()),
+                                opener.span.start,
+                            ));
                         }
+                        return;
+                    }
+                    Some(Token::Punctuation(Punctuation::Semicolon)) => {
+                        self.advance();
+                    }
+                    Some(Token::Punctuation(punctuation)) if Some(*punctuation) == closing => {
+                        self.advance();
+                        return;
+                    }
+                    Some(Token::Punctuation(punctuation)) if punctuation.is_closing() => {
+                        let err = self.unexpected();
+                        self.errors.push(err);
+                        self.advance();
+                        // The mismatched delimiter most likely closes an outer block
+                        if opener.is_some() {
+                            return;
+                        }
+                    }
+                    _ => match self.parse_statement() {
+                        Ok(object) => self.add_object(block, object),
+                        Err(err) => {
+                            self.errors.push(err);
+                            self.synchronize();
+                        }
+                    },
+                }
+            }
+        }
 
-                        continue;
-                    } else if count_unexpected_between(i, '.', code) == 0 {
-                        let attrs_path = slice_until_delimeter(&mut chars);
-                        let attrs_path = format!("{val}{attrs_path}");
-                        let path = attrs_path
-                            .split('.')
-                            .map(|v| v.to_string())
-                            .collect::<Vec<String>>();
-
-                        let var_ref = ParserObject::PropertyRef { path };
-                        let var_ref_key = parser.insert(var_ref);
-
-                        let current_block = parser.get_mut(current_block).unwrap();
-                        current_block.add_object(var_ref_key);
-
-                        last_action = PerfomedAction::ReferencedVariable;
-
-                        continue;
-                    } else if val.is_ascii_digit() {
-                        let item_val = slice_until_delimeter(&mut chars);
-                        let item_val = format!("{val}{item_val}");
-
-                        if let Ok(value) = item_val.parse::<usize>() {
-                            let number_val = ParserObject::NumberVal { value };
-
-                            let number_val_key = parser.insert(number_val);
+        /// Parse statements into a new block, starting at the `opener` delimiter
+        fn parse_block(&mut self, opener: Punctuation) -> Result<ParserObjectKey, ParseError> {
+            let opener_span = self.expect(opener)?;
+            let block = self.new_block(opener_span.start, opener_span.end);
+
+            // Errors inside the block are collected without stopping the outer statement
+            let declared_fns = std::mem::take(&mut self.declared_fns);
+            self.parse_objects(
+                block,
+                Some(SpannedToken {
+                    token: Token::Punctuation(opener),
+                    span: opener_span,
+                }),
+            );
+            self.declared_fns = declared_fns;
 
-                            let current_block = parser.get_mut(current_block).unwrap();
-                            current_block.add_object(number_val_key);
+            let end = self.last_end;
+            self.set_end(block, end);
+            Ok(block)
+        }
 
-                            last_action = PerfomedAction::FoundNumber;
-                        }
+        /// Parse a block of values separated by whitespace, such as call arguments
+        fn parse_values(&mut self, opener: Punctuation) -> Result<ParserObjectKey, ParseError> {
+            let outer = self.separated_values.replace(self.nesting);
+            let block = self.parse_block(opener);
+            self.separated_values = outer;
+            block
+        }
 
-                        continue;
-                    } else {
-                        let item_name = slice_until_delimeter(&mut chars);
-                        let item_name = format!("{val}{item_name}");
+        /// Parse a list of identifiers as variable references
+        fn parse_names(&mut self, opener: Punctuation) -> Result<ParserObjectKey, ParseError> {
+            let opener_span = self.expect(opener)?;
+            let closing = opener.closing().unwrap();
+            let block = self.new_block(opener_span.start, opener_span.end);
 
-                        let var_ref = ParserObject::VarRef {
-                            var_name: item_name,
+            loop {
+                match self.peek_token() {
+                    Some(Token::Identifier(_)) => {
+                        let SpannedToken { token, span } = self.next_token().unwrap();
+                        let Token::Identifier(var_name) = token else {
+                            unreachable!()
                         };
-                        let var_ref_key = parser.insert(var_ref);
+                        let var_ref =
+                            self.insert(ParserObject::VarRef { var_name }, span.start, span.end);
+                        self.add_object(block, var_ref);
+                    }
+                    Some(Token::Punctuation(punctuation)) if *punctuation == closing => {
+                        self.advance();
+                        let end = self.last_end;
+                        self.set_end(block, end);
+                        return Ok(block);
+                    }
+                    Some(_) => {
+                        return Err(self.unexpected());
+                    }
+                    None => {
+                        return Err(self.error(
+                            ParseErrorKind::UnbalancedDelimiter(opener.as_char()),
+                            opener_span.start,
+                        ));
+                    }
+                }
+            }
+        }
 
-                        let current_block = parser.get_mut(current_block).unwrap();
-                        current_block.add_object(var_ref_key);
+        fn parse_statement(&mut self) -> Result<ParserObjectKey, ParseError> {
+            match self.peek_token() {
+                Some(Token::Keyword(Keyword::Let)) => self.parse_var_def(),
+                Some(Token::Keyword(Keyword::Struct)) => self.parse_struct_def(),
+                Some(Token::Keyword(Keyword::Enum)) => self.parse_enum_def(),
+                Some(Token::Keyword(Keyword::Fn)) => {
+                    self.nested(|parser| parser.parse_fn_def(true))
+                }
+                Some(Token::Keyword(Keyword::Break)) => self.parse_break(),
+                Some(Token::Keyword(Keyword::Continue)) => self.parse_continue(),
+                Some(Token::Keyword(Keyword::Return)) => self.parse_return(),
+                _ => {
+                    let expression = self.parse_expression()?;
+                    self.parse_assignment(expression)
+                }
+            }
+        }
 
-                        last_action = PerfomedAction::ReferencedVariable;
+        /// Only variables, properties and the indexes of assignable values can be assigned
+        fn is_assignable(&self, target: ParserObjectKey) -> bool {
+            match &self.objects[target] {
+                ParserObject::VarRef { .. } => true,
+                ParserObject::Index { value, .. } => self.is_assignable(*value),
+                ParserObject::PropertyRef { .. } => true,
+                _ => false,
+            }
+        }
 
-                        continue;
+        /// `target = value` or `target += value`, if the statement continues after `target`
+        fn parse_assignment(
+            &mut self,
+            target: ParserObjectKey,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let operator = match self.peek_token() {
+                Some(Token::Punctuation(Punctuation::Equal)) => None,
+                Some(Token::Operator(operator)) => {
+                    match BinaryOperator::from_compound_assignment(*operator) {
+                        Some(operator) => Some(operator),
+                        None => return Ok(target),
                     }
                 }
+                _ => return Ok(target),
+            };
+
+            if !self.is_assignable(target) {
+                return Err(self.unexpected());
             }
+            self.advance();
+
+            let value = self.parse_expression()?;
+            let (start, end) = (self.spans[target].start, self.spans[value].end);
+            Ok(self.insert(
+                ParserObject::Assignment {
+                    target,
+                    operator,
+                    value,
+                },
+                start,
+                end,
+            ))
         }
 
-        /// Retrieve the global block object
-        pub fn get_global(&self) -> ParserObjectKey {
-            self.global_block
+        /// Consume a keyword that is only allowed where `allowed` is true
+        fn scoped_keyword(&mut self, allowed: bool) -> Result<Span, ParseError> {
+            let SpannedToken { token, span } = self.next_token().unwrap();
+            if !allowed {
+                Err(self.error(
+                    ParseErrorKind::MisplacedKeyword(token.to_string()),
+                    span.start,
+                ))
+            } else {
+                Ok(span)
+            }
         }
 
-        /// Retrieve a ParserObject given a `key`
-        #[inline(always)]
-        pub fn get_object(&self, key: ParserObjectKey) -> Option<&ParserObject> {
-            self.objects.get(key)
+        /// An expression, unless the statement ends
+        fn parse_optional_value(&mut self) -> Result<Option<ParserObjectKey>, ParseError> {
+            let has_value = match self.peek_token() {
+                None | Some(Token::Punctuation(Punctuation::Semicolon)) => false,
+                Some(Token::Punctuation(punctuation)) => !punctuation.is_closing(),
+                Some(_) => true,
+            };
+            if has_value {
+                self.parse_expression().map(Some)
+            } else {
+                Ok(None)
+            }
         }
-    }
-}
 
-pub mod runtime {
-    pub use core::slice::Iter;
-    use std::cell::RefCell;
-    use std::fmt::{Debug, Display};
-    use std::fs::File;
-    use std::io::Read;
-    use std::str::from_utf8;
-    use std::thread::{self};
-    use std::time::Duration;
-    use std::{
-        collections::HashMap,
-        io::{stdout, Write},
-        rc::Rc,
-    };
+        /// `break` or `break value`
+        fn parse_break(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let span = self.scoped_keyword(self.loop_depth > 0)?;
+            let value = self.parse_optional_value()?;
 
-    use slab::Slab;
+            let end = self.last_end;
+            Ok(self.insert(ParserObject::Break { value }, span.start, end))
+        }
 
-    use crate::parser::{Parser, ParserObject};
+        /// `continue`
+        fn parse_continue(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let span = self.scoped_keyword(self.loop_depth > 0)?;
+            Ok(self.insert(ParserObject::Continue, span.start, span.end))
+        }
 
-    pub type LenarResult<T> = Result<T, LenarError>;
+        /// `return` or `return value`
+        fn parse_return(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let span = self.scoped_keyword(self.in_function)?;
+            let value = self.parse_optional_value()?;
 
-    pub struct Execution {
-        pub result: LenarResult<LenarValue>,
-        pub scope_position: usize,
-    }
+            let end = self.last_end;
+            Ok(self.insert(ParserObject::Return { value }, span.start, end))
+        }
 
-    /// A interpreter given a Parser
-    pub struct Runtime;
+        /// `let name = value`
+        fn parse_var_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-    impl Runtime {
-        pub fn run_with_scope(scope: &mut Scope, parser: &Parser) -> Execution {
-            let global_block = parser.get_object(parser.get_global()).unwrap();
-            let result = evaluate_object(global_block, parser, scope, &[], None);
+            let var_name = match self.peek_token() {
+                Some(Token::Identifier(var_name)) => var_name.clone(),
+                _ => return Err(self.unexpected()),
+            };
+            self.advance();
+
+            self.expect(Punctuation::Equal)?;
+
+            let value = self.parse_expression()?;
+            let value_span = self.spans[value];
+            let block_value = self.new_block(value_span.start, value_span.end);
+            self.add_object(block_value, value);
+
+            Ok(self.insert(
+                ParserObject::VarDef {
+                    block_value,
+                    var_name,
+                },
+                start,
+                value_span.end,
+            ))
+        }
 
-            let global_block = parser.get_object(parser.get_global()).unwrap();
-            let objects = global_block
-                .objects()
-                .expect("This is impossible, the global scope is always a block");
+        /// `struct Name { field field }`
+        fn parse_struct_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-            Execution {
-                result,
-                scope_position: objects.len(),
-            }
+            let struct_name = match self.peek_token() {
+                Some(Token::Identifier(struct_name)) => struct_name.clone(),
+                _ => return Err(self.unexpected()),
+            };
+            self.advance();
+
+            let fields = self.parse_fields(Punctuation::OpenBrace)?;
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::StructDef {
+                    struct_name,
+                    fields,
+                },
+                start,
+                end,
+            ))
         }
 
-        pub fn resume_execution(
-            scope: &mut Scope,
-            parser: &Parser,
-            scope_position: usize,
-        ) -> Execution {
-            let global_block = parser.get_object(parser.get_global()).unwrap();
-            let result = evaluate_object(global_block, parser, scope, &[], Some(scope_position));
+        /// `enum Name { Variant(field) Variant }`
+        fn parse_enum_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-            let global_block = parser.get_object(parser.get_global()).unwrap();
-            let objects = global_block
-                .objects()
-                .expect("This is impossible, the global scope is always a block");
+            let enum_name = match self.peek_token() {
+                Some(Token::Identifier(enum_name)) => enum_name.clone(),
+                _ => return Err(self.unexpected()),
+            };
+            self.advance();
 
-            Execution {
-                result,
-                scope_position: objects.len(),
+            let opener = self.expect(Punctuation::OpenBrace)?;
+            let mut variants = Vec::new();
+            loop {
+                match self.peek_token() {
+                    Some(Token::Punctuation(Punctuation::CloseBrace)) => break,
+                    Some(Token::Punctuation(Punctuation::Comma)) if !variants.is_empty() => {
+                        self.advance();
+                    }
+                    Some(Token::Identifier(variant_name)) => {
+                        let variant_name = variant_name.clone();
+                        let span = self.next_token().unwrap().span;
+                        if variants.iter().any(|(name, _)| *name == variant_name) {
+                            return Err(
+                                self.error(ParseErrorKind::DuplicateName(variant_name), span.start)
+                            );
+                        }
+                        let fields = if self.at(Punctuation::OpenParen) {
+                            self.parse_fields(Punctuation::OpenParen)?
+                        } else {
+                            Vec::new()
+                        };
+                        variants.push((variant_name, fields));
+                    }
+                    Some(_) => return Err(self.unexpected()),
+                    None => {
+                        return Err(
+                            self.error(ParseErrorKind::UnbalancedDelimiter('{'), opener.start)
+                        )
+                    }
+                }
             }
+            let end = self.expect(Punctuation::CloseBrace)?.end;
+
+            Ok(self.insert(
+                ParserObject::EnumDef {
+                    enum_name,
+                    variants,
+                },
+                start,
+                end,
+            ))
         }
 
-        /// Evaluate the runtime code and return the exit value
-        pub fn evaluate(parser: &Parser) -> LenarResult<LenarValue> {
-            let mut scope = Scope::default();
-            scope.setup_globals();
+        /// Parse a list of names, optionally separated by commas
+        fn parse_fields(&mut self, opener: Punctuation) -> Result<Vec<String>, ParseError> {
+            let opener_span = self.expect(opener)?;
+            let closing = opener.closing().unwrap();
 
-            Self::run_with_scope(&mut scope, parser).result
+            let mut fields = Vec::new();
+            loop {
+                match self.peek_token() {
+                    Some(Token::Punctuation(punctuation)) if *punctuation == closing => {
+                        self.advance();
+                        return Ok(fields);
+                    }
+                    Some(Token::Punctuation(Punctuation::Comma)) if !fields.is_empty() => {
+                        self.advance();
+                    }
+                    Some(Token::Identifier(field)) => {
+                        let field = field.clone();
+                        let span = self.next_token().unwrap().span;
+                        if fields.contains(&field) {
+                            return Err(
+                                self.error(ParseErrorKind::DuplicateName(field), span.start)
+                            );
+                        }
+                        fields.push(field);
+                    }
+                    Some(_) => return Err(self.unexpected()),
+                    None => {
+                        return Err(self.error(
+                            ParseErrorKind::UnbalancedDelimiter(opener.as_char()),
+                            opener_span.start,
+                        ))
+                    }
+                }
+            }
         }
 
-        pub fn run(code: &str) {
-            let parser = Parser::new(code);
-            Self::evaluate(&parser).ok();
+        fn parse_expression(&mut self) -> Result<ParserObjectKey, ParseError> {
+            self.parse_binary_op(1)
         }
-    }
 
-    /// Runtime values
-    #[derive(Debug, Clone)]
-    pub enum LenarValue {
-        Usize(usize),
-        List(Vec<LenarValue>),
-        Str(String),
-        Byte(u8),
-        Bytes(Vec<u8>),
-        OwnedBytes(Vec<u8>),
-        Void,
-        Bool(bool),
-        Instance(Rc<RefCell<dyn RuntimeInstance>>),
-        Function(Rc<RefCell<dyn RuntimeFunction>>),
-        Enum(LenarEnum),
-        Ref(Rc<RefCell<LenarValue>>),
-    }
+        /// Parse operations whose operators have at least `min_precedence`
+        fn parse_binary_op(&mut self, min_precedence: u8) -> Result<ParserObjectKey, ParseError> {
+            let nesting = self.nesting;
+            let result = self.parse_operations(min_precedence, nesting);
+            self.nesting = nesting;
+            result
+        }
 
-    /// Runtime values
-    #[derive(Debug, Clone)]
-    pub enum LenarError {
-        VariableNotFound(String),
-        WrongValue(String),
-    }
+        /// Each operation holds the previous ones, so every operator of a chain goes one
+        /// level deeper than the last
+        fn parse_operations(
+            &mut self,
+            min_precedence: u8,
+            nesting: usize,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let mut left = self.parse_unary_op()?;
 
-    #[derive(Debug, Clone, Default)]
-    pub struct LenarEnum(HashMap<String, LenarValue>);
+            loop {
+                let operator = match self.peek_token() {
+                    // `a -1` is a subtraction unless it separates two values
+                    Some(Token::Operator(Operator::Negate)) => {
+                        (self.separated_values != Some(nesting)).then_some(BinaryOperator::Sub)
+                    }
+                    Some(Token::Operator(operator)) => BinaryOperator::from_operator(*operator),
+                    _ => None,
+                };
+                let Some(operator) = operator.filter(|op| op.precedence() >= min_precedence) else {
+                    return Ok(left);
+                };
+                let offset = self.next_token().unwrap().span.start;
+                self.deeper(offset)?;
+
+                // Operators are left-associative
+                let right = self.parse_binary_op(operator.precedence() + 1)?;
+                let (start, end) = (self.spans[left].start, self.spans[right].end);
+                left = self.insert(
+                    ParserObject::BinaryOp {
+                        operator,
+                        left,
+                        right,
+                    },
+                    start,
+                    end,
+                );
+            }
+        }
 
-    impl LenarEnum {
-        pub fn new_with_variant(variant_name: String, variant_value: LenarValue) -> Self {
-            let mut en = LenarEnum::default();
-            en.0.insert(variant_name, variant_value);
-            en
+        /// Go one level deeper for an object found at `offset`, up to [`MAX_NESTING_DEPTH`]
+        /// levels. The caller restores the nesting once done.
+        fn deeper(&mut self, offset: usize) -> Result<(), ParseError> {
+            if self.nesting == MAX_NESTING_DEPTH {
+                return Err(self.error(ParseErrorKind::NestingTooDeep, offset));
+            }
+            self.nesting += 1;
+            Ok(())
         }
 
-        pub fn peek_variant(&self, variant_name: &str) -> Option<&LenarValue> {
-            self.0.get(variant_name)
+        /// Parse something one level deeper, up to [`MAX_NESTING_DEPTH`] levels
+        fn nested(
+            &mut self,
+            parse: fn(&mut Self) -> Result<ParserObjectKey, ParseError>,
+        ) -> Result<ParserObjectKey, ParseError> {
+            if self.nesting == MAX_NESTING_DEPTH {
+                let last_end = self.last_end;
+                let offset = self.peek().map_or(last_end, |token| token.span.start);
+                return Err(self.error(ParseErrorKind::NestingTooDeep, offset));
+            }
+
+            self.nesting += 1;
+            let result = parse(self);
+            self.nesting -= 1;
+            result
         }
 
-        pub fn get_variant(mut self, variant_name: &str) -> Option<LenarValue> {
-            self.0.remove(variant_name)
+        /// `!value` or `-value`, every operand goes through here
+        fn parse_unary_op(&mut self) -> Result<ParserObjectKey, ParseError> {
+            self.nested(Self::parse_operand)
         }
-    }
 
-    impl Display for LenarEnum {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            f.write_str(
-                &self
-                    .0
-                    .iter()
-                    .map(|(k, v)| format!("{k}({v})"))
-                    .collect::<Vec<String>>()
-                    .join("\n"),
-            )
+        fn parse_operand(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let operator = match self.peek_token() {
+                Some(Token::Operator(Operator::Bang)) => UnaryOperator::Not,
+                Some(Token::Operator(Operator::Minus | Operator::Negate)) => UnaryOperator::Neg,
+                _ => {
+                    let value = self.parse_primary()?;
+                    return self.parse_index(value);
+                }
+            };
+            let start = self.next_token().unwrap().span.start;
+
+            let value = self.parse_unary_op()?;
+            let end = self.spans[value].end;
+            Ok(self.insert(ParserObject::UnaryOp { operator, value }, start, end))
         }
-    }
 
-    impl Display for LenarValue {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                LenarValue::Usize(u) => f.write_str(&format!("{u}")),
-                LenarValue::List(l) => f
-                    .debug_map()
-                    .value(&l.iter().map(|v| format!("{v}")))
-                    .finish(),
-                LenarValue::Str(s) => f.write_str(s),
-                LenarValue::Byte(b) => f.write_str(from_utf8(&[*b]).unwrap()),
-                LenarValue::Bytes(b) => f.write_str(from_utf8(b).unwrap()),
-                LenarValue::OwnedBytes(b) => f.write_str(from_utf8(b).unwrap()),
-                LenarValue::Void => f.write_str("Void"),
-                LenarValue::Bool(b) => f.write_str(&format!("{b}")),
-                LenarValue::Instance(i) => f.write_str(i.borrow().get_name()),
-                LenarValue::Function(func) => f.write_str(func.borrow().get_name()),
-                LenarValue::Enum(en) => f.write_str(&en.to_string()),
-                LenarValue::Ref(r) => f.write_str(&r.borrow().to_string()),
-            }
+        /// `value[index]` or `value[start:end]`, the bracket must come right after the value.
+        /// Inside functions, values can also be followed by `?`.
+        fn parse_index(&mut self, value: ParserObjectKey) -> Result<ParserObjectKey, ParseError> {
+            let nesting = self.nesting;
+            let result = self.parse_index_chain(value);
+            self.nesting = nesting;
+            result
         }
-    }
 
-    impl PartialEq for LenarValue {
-        fn eq(&self, other: &Self) -> bool {
-            match (self, other) {
-                (Self::Usize(l0), Self::Usize(r0)) => l0 == r0,
-                (Self::List(l0), Self::List(r0)) => l0 == r0,
-                (Self::Str(l0), Self::Str(r0)) => l0 == r0,
-                (Self::Bytes(l0), Self::Bytes(r0)) => l0 == r0,
-                (Self::OwnedBytes(l0), Self::OwnedBytes(r0)) => l0 == r0,
-                (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
-                (Self::Instance(_), Self::Instance(_)) => false,
-                (Self::Function(_), Self::Function(_)) => false,
-                (Self::Void, Self::Void) => true,
-                _ => false,
+        /// Each index or `?` holds the previous value, so every one of them goes one level
+        /// deeper than the last
+        fn parse_index_chain(
+            &mut self,
+            mut value: ParserObjectKey,
+        ) -> Result<ParserObjectKey, ParseError> {
+            loop {
+                if let Some(SpannedToken {
+                    token: Token::Operator(Operator::Question),
+                    span,
+                }) = self.peek().cloned()
+                {
+                    if !self.in_function {
+                        return Err(self.error(
+                            ParseErrorKind::MisplacedOperator("?".to_owned()),
+                            span.start,
+                        ));
+                    }
+                    self.advance();
+                    self.deeper(span.start)?;
+
+                    let start = self.spans[value].start;
+                    value = self.insert(ParserObject::Propagate { value }, start, span.end);
+                    continue;
+                }
+
+                if !self.at_adjacent(Punctuation::OpenBracket) {
+                    break;
+                }
+                let opener = self.next_token().unwrap().span;
+                self.deeper(opener.start)?;
+
+                let index = if self.at(Punctuation::Colon) {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+
+                let object = if self.at(Punctuation::Colon) {
+                    self.advance();
+                    let end = if self.at(Punctuation::CloseBracket) {
+                        None
+                    } else {
+                        Some(self.parse_expression()?)
+                    };
+                    ParserObject::Slice {
+                        value,
+                        start: index,
+                        end,
+                    }
+                } else {
+                    ParserObject::Index {
+                        value,
+                        index: index.unwrap(),
+                    }
+                };
+
+                if self.peek().is_none() {
+                    return Err(self.error(ParseErrorKind::UnbalancedDelimiter('['), opener.start));
+                }
+                let closer = self.expect(Punctuation::CloseBracket)?;
+
+                let start = self.spans[value].start;
+                value = self.insert(object, start, closer.end);
             }
+            Ok(value)
         }
-    }
 
-    impl LenarValue {
-        pub fn is_void(&self) -> bool {
-            matches!(self, Self::Void)
-        }
+        fn parse_primary(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let Some(SpannedToken { token, span }) = self.peek().cloned() else {
+                return Err(self.unexpected());
+            };
 
-        pub fn as_bytes(&self) -> Option<&[u8]> {
-            match self {
-                Self::OwnedBytes(v) => Some(v),
-                Self::Bytes(v) => Some(v),
-                _ => None,
+            match token {
+                Token::String(value) => {
+                    self.advance();
+                    Ok(self.insert(
+                        ParserObject::BytesVal {
+                            value: value.into_bytes(),
+                        },
+                        span.start,
+                        span.end,
+                    ))
+                }
+                Token::Template(parts) => {
+                    self.advance();
+                    self.parse_template(parts, span)
+                }
+                Token::Number(value) => {
+                    self.advance();
+                    Ok(self.insert(ParserObject::NumberVal { value }, span.start, span.end))
+                }
+                Token::Integer(value) => {
+                    self.advance();
+                    Ok(self.insert(ParserObject::IntegerVal { value }, span.start, span.end))
+                }
+                Token::Float(value) => {
+                    self.advance();
+                    Ok(self.insert(ParserObject::FloatVal { value }, span.start, span.end))
+                }
+                Token::Keyword(keyword @ (Keyword::True | Keyword::False)) => {
+                    self.advance();
+                    Ok(self.insert(
+                        ParserObject::BoolVal {
+                            value: keyword == Keyword::True,
+                        },
+                        span.start,
+                        span.end,
+                    ))
+                }
+                Token::Punctuation(Punctuation::OpenBrace) => {
+                    // Maps start with a key, or a colon if they are empty
+                    let is_map = match self.peek_nth_token(1) {
+                        Some(Token::Punctuation(Punctuation::Colon)) => true,
+                        Some(Token::String(_)) => matches!(
+                            self.peek_nth_token(2),
+                            Some(Token::Punctuation(Punctuation::Colon))
+                        ),
+                        _ => false,
+                    };
+                    if is_map {
+                        self.parse_map()
+                    } else {
+                        self.parse_block(Punctuation::OpenBrace)
+                    }
+                }
+                Token::Punctuation(Punctuation::OpenParen) => self.parse_group(),
+                Token::Punctuation(Punctuation::OpenBracket) => {
+                    let items = self.parse_values(Punctuation::OpenBracket)?;
+                    let span = self.spans[items];
+                    Ok(self.insert(ParserObject::ListVal { items }, span.start, span.end))
+                }
+                Token::Keyword(Keyword::Fn) => self.parse_fn_def(false),
+                Token::Keyword(Keyword::If) => self.parse_if_def(),
+                Token::Keyword(Keyword::Match) => self.parse_match_def(),
+                Token::Keyword(Keyword::Try) => self.parse_try_def(),
+                Token::Keyword(Keyword::While) => self.parse_while_def(),
+                Token::Keyword(Keyword::For) => self.parse_for_def(),
+                Token::Identifier(name) => {
+                    self.advance();
+                    if self.at_adjacent(Punctuation::OpenParen) {
+                        self.parse_function_call(name, span.start)
+                    } else if self.at(Punctuation::Dot) {
+                        self.parse_property_ref(name, span.start)
+                    } else {
+                        Ok(self.insert(
+                            ParserObject::VarRef { var_name: name },
+                            span.start,
+                            span.end,
+                        ))
+                    }
+                }
+                _ => Err(self.unexpected()),
             }
         }
 
-        pub fn as_integer(&self) -> Option<usize> {
-            match self {
-                Self::Usize(v) => Some(*v),
-                Self::Ref(v) => v.borrow().as_integer(),
-                _ => None,
+        /// Parse the expressions embedded in a [`Token::Template`]
+        fn parse_template(
+            &mut self,
+            parts: Vec<TemplatePart>,
+            span: Span,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let mut segments = Vec::new();
+            for part in parts {
+                match part {
+                    TemplatePart::Text(text) => segments.push(TemplateSegment::Text(text)),
+                    TemplatePart::Expression { code, offset } => {
+                        let end = offset + code.len();
+                        let expression = self.parse_embedded(offset, end)?;
+                        segments.push(TemplateSegment::Expression(expression));
+                    }
+                }
             }
+            Ok(self.insert(ParserObject::TemplateVal { segments }, span.start, span.end))
         }
 
-        pub fn as_func(&self) -> Option<Rc<RefCell<dyn RuntimeFunction>>> {
-            match self {
-                Self::Function(v) => Some(v.clone()),
-                Self::Ref(v) => v.borrow().as_func(),
-                _ => None,
+        /// Parse the single expression found between the `start` and `end` offsets
+        fn parse_embedded(
+            &mut self,
+            start: usize,
+            end: usize,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let lexer = Lexer::with_offset(&self.code[..end], self.file, start);
+            let outer_lexer = std::mem::replace(&mut self.lexer, lexer);
+            let outer_peeked = self.peeked.take();
+            let outer_last_end = self.last_end;
+
+            let expression = self.parse_expression().and_then(|expression| {
+                if self.peek().is_some() {
+                    Err(self.unexpected())
+                } else {
+                    Ok(expression)
+                }
+            });
+
+            self.lexer = outer_lexer;
+            self.peeked = outer_peeked;
+            self.last_end = outer_last_end;
+
+            // Running out of tokens means reaching the closing brace
+            expression.map_err(|err| match err.kind {
+                ParseErrorKind::UnexpectedEnd => {
+                    self.error(ParseErrorKind::UnexpectedToken("}".to_owned()), end)
+                }
+                _ => err,
+            })
+        }
+
+        /// `{ "key": value, "key": value }`, or `{:}` when empty
+        fn parse_map(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let opener = self.next_token().unwrap().span;
+            let mut entries = Vec::new();
+
+            if self.at(Punctuation::Colon) {
+                self.advance();
+            } else {
+                loop {
+                    let key = match self.peek_token() {
+                        Some(Token::String(key)) => key.clone(),
+                        _ => return Err(self.unexpected()),
+                    };
+                    self.advance();
+                    self.expect(Punctuation::Colon)?;
+                    let value = self.parse_expression()?;
+                    entries.push((key, value));
+
+                    if !self.at(Punctuation::Comma) {
+                        break;
+                    }
+                    self.advance();
+
+                    // Trailing comma
+                    if self.at(Punctuation::CloseBrace) {
+                        break;
+                    }
+                }
+            }
+
+            if self.peek().is_none() {
+                return Err(self.error(ParseErrorKind::UnbalancedDelimiter('{'), opener.start));
             }
+            let closer = self.expect(Punctuation::CloseBrace)?;
+
+            Ok(self.insert(ParserObject::MapVal { entries }, opener.start, closer.end))
         }
 
-        pub fn set_integer(&mut self, integer: usize) {
-            match self {
-                Self::Usize(v) => *v = integer,
-                Self::Ref(r) => r.borrow_mut().set_integer(integer),
-                _ => {}
+        /// `(expression)`
+        fn parse_group(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let opener = self.next_token().unwrap().span;
+            let expression = self.parse_expression()?;
+
+            if self.peek().is_none() {
+                return Err(self.error(ParseErrorKind::UnbalancedDelimiter('('), opener.start));
             }
+            self.expect(Punctuation::CloseParen)?;
+
+            Ok(expression)
         }
-    }
 
-    /// Lenar special objects base
-    pub trait RuntimeInstance: Debug {
-        fn get_props(&self, path: &mut Iter<String>) -> LenarValue {
-            let prop = path.next();
-            if let Some(prop) = prop {
-                self.get_prop(prop)
-            } else {
-                LenarValue::Void
+        /// `name(arguments)`
+        fn parse_function_call(
+            &mut self,
+            fn_name: String,
+            start: usize,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let arguments = self.parse_values(Punctuation::OpenParen)?;
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::FunctionCall { fn_name, arguments },
+                start,
+                end,
+            ))
+        }
+
+        /// `holder.prop.prop`
+        fn parse_property_ref(
+            &mut self,
+            holder: String,
+            start: usize,
+        ) -> Result<ParserObjectKey, ParseError> {
+            let mut path = vec![holder];
+            while self.at(Punctuation::Dot) {
+                self.advance();
+                match self.peek_token() {
+                    Some(Token::Identifier(prop)) => path.push(prop.clone()),
+                    _ => return Err(self.unexpected()),
+                }
+                self.advance();
             }
+            let end = self.last_end;
+            Ok(self.insert(ParserObject::PropertyRef { path }, start, end))
         }
 
-        fn get_prop(&self, prop: &str) -> LenarValue;
+        /// A block wrapped in another one, used as body by functions and conditionals
+        fn parse_body(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let block = self.parse_block(Punctuation::OpenBrace)?;
+            let span = self.spans[block];
+            let body = self.new_block(span.start, span.end);
+            self.add_object(body, block);
+            Ok(body)
+        }
 
-        fn get_name(&self) -> &str;
-    }
+        /// `fn(arguments) [captures] { body }`, or `fn name(arguments) { body }` for
+        /// declarations when `is_statement` is true.
+        fn parse_fn_def(&mut self, is_statement: bool) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-    /// Lenar function base trait
-    pub trait RuntimeFunction: Debug {
-        /// Call the runtime function implementation
-        fn call(&mut self, _args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue>;
+            let fn_name = match self.peek_token() {
+                Some(Token::Identifier(fn_name)) if is_statement => Some(fn_name.clone()),
+                _ => None,
+            };
+            if let Some(fn_name) = &fn_name {
+                let span = self.next_token().unwrap().span;
+                if self.declared_fns.contains(fn_name) {
+                    // The declaration itself is well formed, so keep parsing it
+                    let err =
+                        self.error(ParseErrorKind::DuplicateName(fn_name.clone()), span.start);
+                    self.errors.push(err);
+                } else {
+                    self.declared_fns.push(fn_name.clone());
+                }
+            }
 
-        /// Get the function name
-        fn get_name(&self) -> &str;
-    }
+            let arguments_block = self.parse_names(Punctuation::OpenParen)?;
 
-    /// Runtime Scope that includes variables and nested Scopes.
-    #[derive(Default)]
-    pub struct Scope {
-        variables: HashMap<String, LenarValue>,
-        scopes: HashMap<usize, Scope>,
-    }
+            // The capture area is optional
+            let capture_value = if self.at(Punctuation::OpenBracket) {
+                Some(self.parse_names(Punctuation::OpenBracket)?)
+            } else {
+                None
+            };
 
-    impl Scope {
-        /// Add a [`RuntimeInstance`] to the global scope
-        pub fn add_global_instance(&mut self, val: impl RuntimeInstance + 'static) {
-            self.variables.insert(
-                val.get_name().to_owned(),
-                LenarValue::Instance(Rc::new(RefCell::new(val))),
-            );
+            // Loops don't go through functions
+            let loop_depth = std::mem::take(&mut self.loop_depth);
+            let in_function = std::mem::replace(&mut self.in_function, true);
+            let block_value = self.parse_body();
+            self.loop_depth = loop_depth;
+            self.in_function = in_function;
+            let block_value = block_value?;
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::FnDef {
+                    fn_name,
+                    arguments_block,
+                    block_value,
+                    capture_value,
+                },
+                start,
+                end,
+            ))
         }
 
-        /// Add a [`RuntimeFunction`] to the global scope
-        pub fn add_global_function(&mut self, val: impl RuntimeFunction + 'static) {
-            self.variables.insert(
-                val.get_name().to_owned(),
-                LenarValue::Function(Rc::new(RefCell::new(val))),
-            );
+        /// `if(condition) { body } else if(condition) { body } else { body }`
+        fn parse_if_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
+
+            let condition_block = self.parse_block(Punctuation::OpenParen)?;
+            let block_value = self.parse_body()?;
+
+            let else_block = if self.peek_token() == Some(&Token::Keyword(Keyword::Else)) {
+                self.advance();
+                if self.peek_token() == Some(&Token::Keyword(Keyword::If)) {
+                    Some(self.nested(Self::parse_if_def)?)
+                } else {
+                    Some(self.parse_body()?)
+                }
+            } else {
+                None
+            };
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::IfDef {
+                    condition_block,
+                    block_value,
+                    else_block,
+                },
+                start,
+                end,
+            ))
         }
 
-        /// Define the global variables
-        pub fn setup_globals(&mut self) {
-            let resources_files = Rc::new(RefCell::new(Slab::<File>::new()));
+        /// `try { body } catch (error) { body }`
+        fn parse_try_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-            #[derive(Debug)]
-            struct ToStringFunc {
-                resources_files: Rc<RefCell<Slab<File>>>,
+            let block_value = self.parse_body()?;
+
+            if self.peek_token() != Some(&Token::Keyword(Keyword::Catch)) {
+                return Err(self.unexpected());
             }
+            self.advance();
+
+            let opener = self.expect(Punctuation::OpenParen)?;
+            let var_name = match self.peek_token() {
+                Some(Token::Identifier(var_name)) => var_name.clone(),
+                Some(_) => return Err(self.unexpected()),
+                None => {
+                    return Err(self.error(ParseErrorKind::UnbalancedDelimiter('('), opener.start))
+                }
+            };
+            self.advance();
+            self.expect(Punctuation::CloseParen)?;
+
+            let catch_block = self.parse_body()?;
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::TryDef {
+                    block_value,
+                    var_name,
+                    catch_block,
+                },
+                start,
+                end,
+            ))
+        }
 
-            impl ToStringFunc {
-                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
-                    Self { resources_files }
+        /// `match value { pattern => value, _ => value }`
+        fn parse_match_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
+
+            let value = self.parse_expression()?;
+
+            let opener = self.expect(Punctuation::OpenBrace)?;
+            let mut arms = Vec::new();
+            loop {
+                match self.peek_token() {
+                    Some(Token::Punctuation(Punctuation::CloseBrace)) => break,
+                    Some(Token::Punctuation(Punctuation::Comma)) if !arms.is_empty() => {
+                        self.advance();
+                    }
+                    Some(_) => arms.push(self.parse_match_arm()?),
+                    None => {
+                        return Err(
+                            self.error(ParseErrorKind::UnbalancedDelimiter('{'), opener.start)
+                        )
+                    }
                 }
             }
+            let end = self.expect(Punctuation::CloseBrace)?.end;
 
-            impl RuntimeFunction for ToStringFunc {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    match args[0] {
-                        LenarValue::Usize(rid) => {
-                            let resources_files = self.resources_files.borrow_mut();
-                            let mut file = resources_files.get(rid).unwrap();
-                            let mut buf = Vec::new();
-                            file.read_to_end(&mut buf).unwrap();
-                            Ok(LenarValue::OwnedBytes(buf))
-                        }
-                        _ => Ok(LenarValue::Void),
+            Ok(self.insert(ParserObject::MatchDef { value, arms }, start, end))
+        }
+
+        /// `pattern => value`
+        fn parse_match_arm(&mut self) -> Result<MatchArm, ParseError> {
+            let pattern = match self.peek_token() {
+                Some(Token::Identifier(name)) if name == "_" => {
+                    self.advance();
+                    MatchPattern::Wildcard
+                }
+                Some(Token::Identifier(variant_name)) => {
+                    let variant_name = variant_name.clone();
+                    self.advance();
+                    let bindings = if self.at(Punctuation::OpenParen) {
+                        self.parse_fields(Punctuation::OpenParen)?
+                    } else {
+                        Vec::new()
+                    };
+                    MatchPattern::Variant {
+                        variant_name,
+                        bindings,
                     }
                 }
+                Some(
+                    Token::Number(_)
+                    | Token::Integer(_)
+                    | Token::Float(_)
+                    | Token::String(_)
+                    | Token::Keyword(Keyword::True | Keyword::False),
+                ) => MatchPattern::Literal(self.parse_primary()?),
+                _ => return Err(self.unexpected()),
+            };
 
-                fn get_name(&self) -> &str {
-                    "toString"
-                }
+            if self.peek_token() != Some(&Token::Operator(Operator::FatArrow)) {
+                return Err(self.unexpected());
             }
+            self.advance();
 
-            #[derive(Debug)]
-            struct OpenFileFunc {
-                resources_files: Rc<RefCell<Slab<File>>>,
-            }
+            let value = self.parse_expression()?;
+            let value_span = self.spans[value];
+            let block_value = self.new_block(value_span.start, value_span.end);
+            self.add_object(block_value, value);
 
-            impl OpenFileFunc {
-                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
-                    Self { resources_files }
-                }
-            }
+            Ok(MatchArm {
+                pattern,
+                block_value,
+            })
+        }
 
-            impl RuntimeFunction for OpenFileFunc {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let file_path = args[0].as_bytes().unwrap();
-                    let file_path = from_utf8(file_path).unwrap();
-                    let file = File::open(file_path).unwrap();
+        /// The body of a loop
+        fn parse_loop_body(&mut self) -> Result<ParserObjectKey, ParseError> {
+            self.loop_depth += 1;
+            let body = self.parse_body();
+            self.loop_depth -= 1;
+            body
+        }
 
-                    let mut resources_files = self.resources_files.borrow_mut();
-                    let rid = resources_files.insert(file);
+        /// `while(condition) { body }`
+        fn parse_while_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
+
+            let condition_block = self.parse_block(Punctuation::OpenParen)?;
+            let block_value = self.parse_loop_body()?;
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::WhileDef {
+                    condition_block,
+                    block_value,
+                },
+                start,
+                end,
+            ))
+        }
 
-                    Ok(LenarValue::Usize(rid))
-                }
+        /// `for name in iterable { body }`
+        fn parse_for_def(&mut self) -> Result<ParserObjectKey, ParseError> {
+            let start = self.next_token().unwrap().span.start;
 
-                fn get_name(&self) -> &str {
-                    "openFile"
-                }
+            let var_name = match self.peek_token() {
+                Some(Token::Identifier(var_name)) => var_name.clone(),
+                _ => return Err(self.unexpected()),
+            };
+            self.advance();
+
+            if self.peek_token() != Some(&Token::Keyword(Keyword::In)) {
+                return Err(self.unexpected());
             }
+            self.advance();
+
+            let iterable = self.parse_expression()?;
+            let block_value = self.parse_loop_body()?;
+
+            let end = self.last_end;
+            Ok(self.insert(
+                ParserObject::ForDef {
+                    var_name,
+                    iterable,
+                    block_value,
+                },
+                start,
+                end,
+            ))
+        }
+    }
 
-            #[derive(Debug)]
-            struct LenarGlobal;
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-            impl RuntimeInstance for LenarGlobal {
-                fn get_prop(&self, prop: &str) -> LenarValue {
-                    match prop {
-                        "version" => LenarValue::Str("1.0.0".to_string()),
-                        _ => LenarValue::Void,
-                    }
-                }
+        fn parse_errors(code: &str) -> Vec<ParseError> {
+            Parser::new(code).err().unwrap_or_default()
+        }
 
-                fn get_name(&self) -> &str {
-                    "Lenar"
-                }
+        /// Objects of the global block
+        fn statements(parser: &Parser) -> Vec<&ParserObject> {
+            let global = parser.get_object(parser.get_global()).unwrap();
+            global
+                .objects()
+                .unwrap()
+                .iter()
+                .map(|key| parser.get_object(*key).unwrap())
+                .collect()
+        }
+
+        #[test]
+        fn syntax_errors() {
+            use ParseErrorKind::*;
+
+            // Code, kind of the first error, and its offset, line and column
+            let cases = [
+                ("let x = ;", UnexpectedToken(";".to_owned()), (8, 1, 9)),
+                ("1 @ 2", UnexpectedToken("@".to_owned()), (2, 1, 3)),
+                ("let x =", UnexpectedEnd, (7, 1, 8)),
+                ("let a = \"abc", UnterminatedString, (8, 1, 9)),
+                ("\"\\q\"", InvalidEscape, (1, 1, 2)),
+                ("/* open", UnterminatedComment, (0, 1, 1)),
+                ("let x = 99999999999999999999999", InvalidNumber, (8, 1, 9)),
+                ("print(1", UnbalancedDelimiter('('), (5, 1, 6)),
+                ("print(1))", UnbalancedDelimiter(')'), (8, 1, 9)),
+                ("{ 1 }}", UnbalancedDelimiter('}'), (5, 1, 6)),
+                ("1 = 2", MisplacedOperator("=".to_owned()), (2, 1, 3)),
+                (
+                    "let x = 1\nlet = 2",
+                    MisplacedOperator("=".to_owned()),
+                    (14, 2, 5),
+                ),
+                ("break", MisplacedKeyword("break".to_owned()), (0, 1, 1)),
+                ("return 1", MisplacedKeyword("return".to_owned()), (0, 1, 1)),
+                (
+                    "while (true) { fn() [] { continue } }",
+                    MisplacedKeyword("continue".to_owned()),
+                    (25, 1, 26),
+                ),
+                (
+                    "struct P { x y x }",
+                    DuplicateName("x".to_owned()),
+                    (15, 1, 16),
+                ),
+                (
+                    "enum E { A(x x) }",
+                    DuplicateName("x".to_owned()),
+                    (13, 1, 14),
+                ),
+                (
+                    "enum E { A B(x) A }",
+                    DuplicateName("A".to_owned()),
+                    (16, 1, 17),
+                ),
+                (
+                    "fn f() {}\nfn f() {}",
+                    DuplicateName("f".to_owned()),
+                    (13, 2, 4),
+                ),
+                (
+                    "fn g() { fn f() {} fn f() {} }",
+                    DuplicateName("f".to_owned()),
+                    (22, 1, 23),
+                ),
+                // Columns count characters, not bytes
+                ("let x = 2\n  \"é\" }", UnbalancedDelimiter('}'), (17, 2, 7)),
+            ];
+
+            for (code, kind, (offset, line, column)) in cases {
+                let error = ParseError {
+                    kind,
+                    offset,
+                    line,
+                    column,
+                };
+                assert_eq!(parse_errors(code).first(), Some(&error), "{code}");
             }
 
-            // `print()`
-            #[derive(Debug)]
-            struct PrintFunc;
+            // Functions in different blocks may share a name
+            Parser::new("fn f() {} fn g() { fn f() {} } fn h() { fn f() {} }").unwrap();
+        }
 
-            impl PrintFunc {
-                pub fn write(value: &LenarValue) {
-                    match value {
-                        LenarValue::OwnedBytes(bts) => {
-                            stdout().write(bts).ok();
-                        }
-                        LenarValue::Byte(b) => {
-                            stdout().write(&[*b]).ok();
-                        }
-                        LenarValue::Bytes(bts) => {
-                            stdout().write(bts).ok();
-                        }
-                        LenarValue::Function(func) => {
-                            stdout().write(func.borrow().get_name().as_bytes()).ok();
-                        }
-                        LenarValue::Instance(instance) => {
-                            stdout().write(instance.borrow().get_name().as_bytes()).ok();
-                        }
-                        LenarValue::Bool(b) => {
-                            stdout().write(b.to_string().as_bytes()).ok();
-                        }
-                        LenarValue::Usize(n) => {
-                            stdout().write(n.to_string().as_bytes()).ok();
-                        }
-                        LenarValue::Str(s) => {
-                            stdout().write(s.as_bytes()).ok();
-                        }
-                        LenarValue::List(l) => {
-                            l.iter().for_each(Self::write);
-                        }
-                        LenarValue::Void => {
-                            stdout().write("Void".as_bytes()).ok();
-                        }
-                        LenarValue::Enum(en) => {
-                            stdout().write(en.to_string().as_bytes()).ok();
-                        }
-                        LenarValue::Ref(r) => {
-                            stdout().write(r.borrow().to_string().as_bytes()).ok();
+        #[test]
+        fn failed_parse_rolls_back() {
+            let mut parser = Parser::new("let a = 1 // first").unwrap();
+            let objects = parser.objects.len();
+
+            assert!(parser.parse("let b = 2; let c = ) // second").is_err());
+            assert_eq!(parser.objects.len(), objects);
+            assert_eq!(statements(&parser).len(), 1);
+            assert_eq!(parser.get_comments().len(), 1);
+
+            // Later code is parsed as usual
+            parser.parse("let d = 3").unwrap();
+            let names = statements(&parser)
+                .into_iter()
+                .map(|object| match object {
+                    ParserObject::VarDef { var_name, .. } => var_name.as_str(),
+                    _ => panic!("Expected only variables"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["a", "d"]);
+        }
+
+        #[test]
+        fn spans_point_into_their_file() {
+            let mut parser = Parser::new("let x = 1;").unwrap();
+            parser.parse("\nprint(x \"hi\");").unwrap();
+
+            let global = parser.get_object(parser.get_global()).unwrap();
+            let texts = global
+                .objects()
+                .unwrap()
+                .iter()
+                .map(|key| {
+                    let span = parser.get_span(*key).unwrap();
+                    let source = parser.get_source(span.file).unwrap();
+                    (span.file, &source[span.start..span.end])
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(texts, [(0, "let x = 1"), (1, "print(x \"hi\")")]);
+
+            let call = global.objects().unwrap()[1];
+            let span = parser.get_span(call).unwrap();
+            assert_eq!(parser.get_location(span), Some((2, 1)));
+        }
+
+        #[test]
+        fn keywords_need_word_boundaries() {
+            let parser = Parser::new("let letter = 1; iffy(letter)").unwrap();
+            assert!(matches!(
+                statements(&parser)[..],
+                [
+                    ParserObject::VarDef { var_name, .. },
+                    ParserObject::FunctionCall { fn_name, .. },
+                ] if var_name == "letter" && fn_name == "iffy"
+            ));
+        }
+
+        #[test]
+        fn comments_are_kept_apart() {
+            let code = "// greeting\nprint(/* who */ \"world\") /* a\nb */ print(1) // end";
+            let parser = Parser::new(code).unwrap();
+
+            assert!(matches!(
+                statements(&parser)[..],
+                [
+                    ParserObject::FunctionCall { .. },
+                    ParserObject::FunctionCall { .. }
+                ]
+            ));
+
+            let comments = parser
+                .get_comments()
+                .iter()
+                .map(|comment| (comment.token.to_string(), comment.span.start))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                comments,
+                [
+                    ("// greeting".to_owned(), 0),
+                    ("/* who */".to_owned(), 18),
+                    ("/* a\nb */".to_owned(), 37),
+                    ("// end".to_owned(), 56),
+                ]
+            );
+        }
+
+        #[test]
+        fn map_literals_and_blocks() {
+            let cases = [
+                ("{ \"a\": 1, \"b\": 2 }", Some(2)),
+                ("{:}", Some(0)),
+                ("{ \"a\" }", None),
+                ("{ a }", None),
+                ("{}", None),
+            ];
+
+            for (code, entries_len) in cases {
+                let parser = Parser::new(code).unwrap();
+                let found = match statements(&parser)[..] {
+                    [ParserObject::MapVal { entries }] => Some(entries.len()),
+                    [ParserObject::Block { .. }] => None,
+                    _ => panic!("Unexpected objects for {code}"),
+                };
+                assert_eq!(found, entries_len, "{code}");
+            }
+        }
+
+        #[test]
+        fn template_errors_point_into_the_string() {
+            let errors = parse_errors("let s = \"a ${1 +} b\"");
+            assert_eq!(
+                errors[0].kind,
+                ParseErrorKind::UnexpectedToken("}".to_owned())
+            );
+            assert_eq!(errors[0].offset, 16);
+
+            let errors = parse_errors("let s = \"a ${}\"");
+            assert_eq!(errors[0].offset, 13);
+        }
+
+        #[test]
+        fn deep_nesting_is_an_error() {
+            for (opener, closer) in [("(", ")"), ("{", "}"), ("[", "]"), ("\"${", "}\"")] {
+                let code = format!("{}1{}", opener.repeat(3000), closer.repeat(3000));
+                let errors = parse_errors(&code);
+                assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep, "{opener}");
+            }
+
+            let code = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+            assert!(Parser::new(&code).is_ok());
+        }
+
+        #[test]
+        fn long_operator_chains_are_an_error() {
+            let code = format!("let a = 1{}", " + 1".repeat(5000));
+            let errors = parse_errors(&code);
+            assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep);
+
+            let code = format!("let a = 1{}", " + 1".repeat(200));
+            assert!(Parser::new(&code).is_ok());
+        }
+
+        #[test]
+        fn long_index_chains_are_an_error() {
+            for index in ["[0]", "[0:]"] {
+                let code = format!("let a = x{}", index.repeat(200_000));
+                let errors = parse_errors(&code);
+                assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep, "{index}");
+            }
+
+            let code = format!("let a = x{}", "[0]".repeat(200));
+            assert!(Parser::new(&code).is_ok());
+        }
+
+        #[test]
+        fn long_propagation_chains_are_an_error() {
+            let code = format!("fn f(r) {{ r{} }}", "?".repeat(20_000));
+            let errors = parse_errors(&code);
+            assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep);
+
+            let code = format!("fn f(r) {{ r{} }}", "?".repeat(200));
+            assert!(Parser::new(&code).is_ok());
+        }
+
+        #[test]
+        fn negative_numbers_after_values() {
+            let parser = Parser::new("let b = a -1").unwrap();
+            let [ParserObject::VarDef { block_value, .. }] = statements(&parser)[..] else {
+                panic!("Expected a single variable")
+            };
+            let value = parser.get_object(*block_value).unwrap().objects().unwrap()[0];
+            assert!(matches!(
+                parser.get_object(value),
+                Some(ParserObject::BinaryOp {
+                    operator: BinaryOperator::Sub,
+                    ..
+                })
+            ));
+
+            // Arguments and list items are separated instead
+            for code in ["f(a -1)", "[a -1]"] {
+                let parser = Parser::new(code).unwrap();
+                let items = match statements(&parser)[..] {
+                    [ParserObject::FunctionCall { arguments, .. }] => *arguments,
+                    [ParserObject::ListVal { items }] => *items,
+                    _ => panic!("Unexpected objects for {code}"),
+                };
+                let items = parser.get_object(items).unwrap().objects().unwrap();
+                assert_eq!(items.len(), 2, "{code}");
+            }
+        }
+    }
+}
+
+pub mod runtime {
+    pub use core::slice::Iter;
+    use std::cell::{Cell, RefCell};
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+    use std::fmt::{Debug, Display};
+    use std::fs::File;
+    use std::io::{self, BufReader, Read};
+    use std::thread::{self};
+    use std::time::Duration;
+    use std::{
+        collections::{HashMap, HashSet},
+        io::{stdout, Write},
+        rc::{Rc, Weak},
+        sync::Arc,
+    };
+
+    use slab::Slab;
+
+    use crate::parser::{
+        BinaryOperator, MatchArm, MatchPattern, Parser, ParserObject, ParserObjectKey, Span,
+        TemplateSegment, UnaryOperator,
+    };
+
+    pub type LenarResult<T> = Result<T, LenarError>;
+
+    /// Storage of a variable, shared by the scope and the functions that can see it
+    type Binding = Rc<RefCell<LenarValue>>;
+
+    pub struct Execution {
+        pub result: LenarResult<LenarValue>,
+        pub scope_position: usize,
+    }
+
+    /// A interpreter given a Parser
+    pub struct Runtime;
+
+    impl Runtime {
+        pub fn run_with_scope(scope: &mut Scope, parser: &Parser) -> Execution {
+            let max_evaluation_depth = scope
+                .max_evaluation_depth
+                .unwrap_or(DEFAULT_MAX_EVALUATION_DEPTH);
+            let result = with_max_evaluation_depth(max_evaluation_depth, || {
+                evaluate_object(parser.get_global(), parser, scope, &[], None)
+            })
+            .map_err(Unwind::into_error);
+
+            let global_block = parser.get_object(parser.get_global()).unwrap();
+            let objects = global_block
+                .objects()
+                .expect("This is impossible, the global scope is always a block");
+
+            Execution {
+                result,
+                scope_position: objects.len(),
+            }
+        }
+
+        pub fn resume_execution(
+            scope: &mut Scope,
+            parser: &Parser,
+            scope_position: usize,
+        ) -> Execution {
+            let max_evaluation_depth = scope
+                .max_evaluation_depth
+                .unwrap_or(DEFAULT_MAX_EVALUATION_DEPTH);
+            let result = with_max_evaluation_depth(max_evaluation_depth, || {
+                evaluate_object(
+                    parser.get_global(),
+                    parser,
+                    scope,
+                    &[],
+                    Some(scope_position),
+                )
+            })
+            .map_err(Unwind::into_error);
+
+            let global_block = parser.get_object(parser.get_global()).unwrap();
+            let objects = global_block
+                .objects()
+                .expect("This is impossible, the global scope is always a block");
+
+            Execution {
+                result,
+                scope_position: objects.len(),
+            }
+        }
+
+        /// Evaluate the runtime code and return the exit value
+        pub fn evaluate(parser: &Parser) -> LenarResult<LenarValue> {
+            let mut scope = Scope::default();
+            scope.setup_globals();
+
+            Self::run_with_scope(&mut scope, parser).result
+        }
+
+        pub fn run(code: &str) {
+            if let Ok(parser) = Parser::new(code) {
+                Self::evaluate(&parser).ok();
+            }
+        }
+    }
+
+    /// Runtime values
+    #[derive(Debug, Clone)]
+    pub enum LenarValue {
+        Usize(usize),
+        Integer(i64),
+        Float(f64),
+        List(Vec<LenarValue>),
+        Map(BTreeMap<String, LenarValue>),
+        Str(String),
+        Byte(u8),
+        Bytes(Vec<u8>),
+        OwnedBytes(Vec<u8>),
+        Void,
+        Bool(bool),
+        Instance(Rc<RefCell<dyn RuntimeInstance>>),
+        Function(Rc<RefCell<dyn RuntimeFunction>>),
+        Enum(LenarEnum),
+        Ref(Rc<RefCell<LenarValue>>),
+    }
+
+    /// Errors that stop the evaluation of a program, unless a `catch` block handles them
+    #[derive(Debug, Clone)]
+    pub enum LenarError {
+        VariableNotFound(String),
+        WrongValue(String),
+        /// A value of another type was expected
+        TypeMismatch {
+            expected: String,
+            actual: String,
+        },
+        /// A function was called with less arguments than it needs
+        ArityMismatch {
+            fn_name: String,
+            expected: usize,
+            actual: usize,
+        },
+        /// Call to a variable that isn't a function
+        NotCallable(String),
+        /// Call to a function that is running and can't be called again until it returns
+        AlreadyRunning,
+        /// The result of an arithmetic operation can't be represented
+        Overflow(String),
+        DivisionByZero,
+        /// Comparison with a NaN float, which has no order
+        Unordered {
+            left: f64,
+            right: f64,
+        },
+        /// Access to a position past the end of a list
+        IndexOutOfRange {
+            index: usize,
+            len: usize,
+        },
+        /// Write to a property that doesn't exist or is read-only
+        PropertyNotAssignable {
+            holder: String,
+            property: String,
+        },
+        /// Value given to `throw()`
+        UserThrown(ThrownValue),
+        Io(Arc<io::Error>),
+        /// The program went past one of the runtime limits, such as the one
+        /// of [`Scope::set_max_evaluation_depth`]
+        ResourceLimitExceeded {
+            resource: String,
+            limit: usize,
+        },
+        /// An error that went through one or more function calls
+        Traced {
+            error: Box<LenarError>,
+            /// Innermost call first
+            stack: Vec<StackFrame>,
+        },
+    }
+
+    impl LenarError {
+        /// Name of the kind of error, as seen by `catch` blocks
+        pub fn kind(&self) -> &'static str {
+            match self.root() {
+                Self::VariableNotFound(_) => "VariableNotFound",
+                Self::WrongValue(_) => "WrongValue",
+                Self::TypeMismatch { .. } => "TypeMismatch",
+                Self::ArityMismatch { .. } => "ArityMismatch",
+                Self::NotCallable(_) => "NotCallable",
+                Self::AlreadyRunning => "AlreadyRunning",
+                Self::Overflow(_) => "Overflow",
+                Self::DivisionByZero => "DivisionByZero",
+                Self::Unordered { .. } => "Unordered",
+                Self::IndexOutOfRange { .. } => "IndexOutOfRange",
+                Self::PropertyNotAssignable { .. } => "PropertyNotAssignable",
+                Self::UserThrown(_) => "UserThrown",
+                Self::Io(_) => "Io",
+                Self::ResourceLimitExceeded { .. } => "ResourceLimitExceeded",
+                Self::Traced { error, .. } => error.kind(),
+            }
+        }
+
+        /// Error for a value whose type isn't the `expected` one
+        pub fn type_mismatch(expected: &str, actual: &LenarValue) -> Self {
+            Self::TypeMismatch {
+                expected: expected.to_owned(),
+                actual: actual.type_name(),
+            }
+        }
+
+        /// Get the error without its stack trace
+        pub fn root(&self) -> &LenarError {
+            match self {
+                Self::Traced { error, .. } => error,
+                err => err,
+            }
+        }
+
+        /// Get the function calls this error went through, innermost call first
+        pub fn stack(&self) -> &[StackFrame] {
+            match self {
+                Self::Traced { stack, .. } => stack,
+                _ => &[],
+            }
+        }
+
+        /// Add an outer function call to the stack trace
+        pub fn with_frame(self, frame: StackFrame) -> Self {
+            match self {
+                Self::Traced { error, mut stack } => {
+                    stack.push(frame);
+                    Self::Traced { error, stack }
+                }
+                error => Self::Traced {
+                    error: Box::new(error),
+                    stack: vec![frame],
+                },
+            }
+        }
+    }
+
+    impl Display for LenarError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::VariableNotFound(name) => write!(f, "Variable `{name}` not found"),
+                Self::WrongValue(expected) => write!(f, "Wrong value, expected `{expected}`"),
+                Self::TypeMismatch { expected, actual } => {
+                    write!(
+                        f,
+                        "Type mismatch, expected `{expected}` but found `{actual}`"
+                    )
+                }
+                Self::ArityMismatch {
+                    fn_name,
+                    expected,
+                    actual,
+                } => write!(
+                    f,
+                    "Function `{fn_name}` needs {expected} argument(s) but got {actual}"
+                ),
+                Self::NotCallable(name) => write!(f, "`{name}` is not a function"),
+                Self::AlreadyRunning => f.write_str("The function is already running"),
+                Self::Overflow(operation) => write!(f, "Arithmetic overflow in `{operation}`"),
+                Self::DivisionByZero => f.write_str("Division by zero"),
+                Self::Unordered { left, right } => {
+                    write!(f, "`{left}` and `{right}` can't be ordered")
+                }
+                Self::IndexOutOfRange { index, len } => {
+                    write!(f, "Index {index} out of range for length {len}")
+                }
+                Self::PropertyNotAssignable { holder, property } => {
+                    write!(f, "Property `{property}` of `{holder}` can't be assigned")
+                }
+                Self::UserThrown(value) => write!(f, "Uncaught error: {value}"),
+                Self::Io(err) => write!(f, "IO error: {err}"),
+                Self::ResourceLimitExceeded { resource, limit } => {
+                    write!(f, "Exceeded the limit of {limit} {resource}")
+                }
+                Self::Traced { error, stack } => {
+                    write!(f, "{error}")?;
+                    f.write_str("\nStack backtrace:")?;
+                    for (i, frame) in stack.iter().enumerate() {
+                        write!(f, "\n{i:>4}: {frame}")?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for LenarError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Self::Io(err) => Some(err.as_ref()),
+                Self::Traced { error, .. } => Some(error.as_ref()),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<io::Error> for LenarError {
+        fn from(err: io::Error) -> Self {
+            Self::Io(Arc::new(err))
+        }
+    }
+
+    /// Copy of a value given to `throw()` that can be sent to other threads, functions
+    /// and instances without fields are kept as their text
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ThrownValue {
+        Usize(usize),
+        Integer(i64),
+        Float(f64),
+        List(Vec<ThrownValue>),
+        Map(BTreeMap<String, ThrownValue>),
+        Str(String),
+        Byte(u8),
+        Void,
+        Bool(bool),
+        Enum(Vec<(String, ThrownValue)>),
+    }
+
+    impl From<&LenarValue> for ThrownValue {
+        fn from(value: &LenarValue) -> Self {
+            Self::copy(value, &mut Vec::new())
+        }
+    }
+
+    impl ThrownValue {
+        /// Copy a value, `instances` are the ones being copied so those that hold
+        /// themselves are kept as their text
+        fn copy(value: &LenarValue, instances: &mut Vec<*const ()>) -> Self {
+            match value {
+                LenarValue::Usize(n) => Self::Usize(*n),
+                LenarValue::Integer(n) => Self::Integer(*n),
+                LenarValue::Float(n) => Self::Float(*n),
+                LenarValue::List(items) => Self::List(
+                    items
+                        .iter()
+                        .map(|item| Self::copy(item, instances))
+                        .collect(),
+                ),
+                LenarValue::Map(entries) => Self::Map(
+                    entries
+                        .iter()
+                        .map(|(key, value)| (key.clone(), Self::copy(value, instances)))
+                        .collect(),
+                ),
+                LenarValue::Byte(b) => Self::Byte(*b),
+                LenarValue::Void => Self::Void,
+                LenarValue::Bool(b) => Self::Bool(*b),
+                LenarValue::Enum(variants) => Self::Enum(
+                    variants
+                        .0
+                        .iter()
+                        .map(|(name, payload)| (name.clone(), Self::copy(payload, instances)))
+                        .collect(),
+                ),
+                LenarValue::Ref(v) => Self::copy(&v.borrow(), instances),
+                LenarValue::Instance(instance) => {
+                    let pointer = Rc::as_ptr(instance).cast::<()>();
+                    let fields = instance.borrow().get_fields();
+                    match fields {
+                        Some(fields) if !instances.contains(&pointer) => {
+                            instances.push(pointer);
+                            let fields = fields
+                                .iter()
+                                .map(|(field, value)| (field.clone(), Self::copy(value, instances)))
+                                .collect();
+                            instances.pop();
+                            Self::Map(fields)
                         }
+                        _ => Self::Str(value.to_string()),
+                    }
+                }
+                value => Self::Str(value.to_string()),
+            }
+        }
+    }
+
+    impl From<ThrownValue> for LenarValue {
+        fn from(value: ThrownValue) -> Self {
+            match value {
+                ThrownValue::Usize(n) => Self::Usize(n),
+                ThrownValue::Integer(n) => Self::Integer(n),
+                ThrownValue::Float(n) => Self::Float(n),
+                ThrownValue::List(items) => Self::List(items.into_iter().map(Self::from).collect()),
+                ThrownValue::Map(entries) => Self::Map(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key, Self::from(value)))
+                        .collect(),
+                ),
+                ThrownValue::Str(text) => Self::Str(text),
+                ThrownValue::Byte(b) => Self::Byte(b),
+                ThrownValue::Void => Self::Void,
+                ThrownValue::Bool(b) => Self::Bool(b),
+                ThrownValue::Enum(variants) => Self::Enum(LenarEnum(
+                    variants
+                        .into_iter()
+                        .map(|(name, payload)| (name, Self::from(payload)))
+                        .collect(),
+                )),
+            }
+        }
+    }
+
+    impl Display for ThrownValue {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", LenarValue::from(self.clone()))
+        }
+    }
+
+    /// Kind of a [`RuntimeFunction`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FunctionKind {
+        /// Implemented in Rust
+        Native,
+        /// Defined in lenar code
+        Closure,
+        /// Creates the values of a type declared in lenar code
+        Constructor,
+    }
+
+    /// A function call a [`LenarError`] went through
+    #[derive(Debug, Clone)]
+    pub struct StackFrame {
+        /// Name the function was called with
+        pub fn_name: String,
+        /// Span of the call site
+        pub span: Span,
+        /// Line and column of the call site
+        pub location: Option<(usize, usize)>,
+        pub kind: FunctionKind,
+    }
+
+    impl Display for StackFrame {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let kind = match self.kind {
+                FunctionKind::Native => "native",
+                FunctionKind::Closure => "closure",
+                FunctionKind::Constructor => "constructor",
+            };
+            write!(f, "{} ({kind})", self.fn_name)?;
+            if let Some((line, column)) = self.location {
+                write!(
+                    f,
+                    " at file {}, line {line}, column {column}",
+                    self.span.file
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    pub struct LenarEnum(HashMap<String, LenarValue>);
+
+    impl LenarEnum {
+        pub fn new_with_variant(variant_name: String, variant_value: LenarValue) -> Self {
+            let mut en = LenarEnum::default();
+            en.0.insert(variant_name, variant_value);
+            en
+        }
+
+        pub fn peek_variant(&self, variant_name: &str) -> Option<&LenarValue> {
+            self.0.get(variant_name)
+        }
+
+        pub fn get_variant(mut self, variant_name: &str) -> Option<LenarValue> {
+            self.0.remove(variant_name)
+        }
+
+        /// Get the payload of an `Ok` or a `Some`
+        pub fn peek_success(&self) -> Option<&LenarValue> {
+            self.peek_variant("Ok")
+                .or_else(|| self.peek_variant("Some"))
+        }
+
+        /// Whether this is an `Err` or a `None`
+        pub fn is_failure(&self) -> bool {
+            self.peek_variant("Err").is_some() || self.peek_variant("None").is_some()
+        }
+    }
+
+    impl Display for LenarEnum {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(
+                &self
+                    .0
+                    .iter()
+                    .map(|(k, v)| match v {
+                        // Variants without payload
+                        LenarValue::Void => k.to_owned(),
+                        v => format!("{k}({v})"),
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            )
+        }
+    }
+
+    impl Display for LenarValue {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                LenarValue::Usize(u) => f.write_str(&format!("{u}")),
+                LenarValue::Integer(i) => f.write_str(&format!("{i}")),
+                LenarValue::Float(n) => f.write_str(&format!("{n}")),
+                LenarValue::List(l) => {
+                    f.write_str("[")?;
+                    for (i, item) in l.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(", ")?;
+                        }
+                        write!(f, "{item}")?;
+                    }
+                    f.write_str("]")
+                }
+                LenarValue::Map(m) => {
+                    f.write_str("{")?;
+                    for (i, (key, value)) in m.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(", ")?;
+                        }
+                        write!(f, "{key}: {value}")?;
+                    }
+                    f.write_str("}")
+                }
+                LenarValue::Str(s) => f.write_str(s),
+                LenarValue::Byte(b) => f.write_str(&String::from_utf8_lossy(&[*b])),
+                LenarValue::Bytes(b) => f.write_str(&String::from_utf8_lossy(b)),
+                LenarValue::OwnedBytes(b) => f.write_str(&String::from_utf8_lossy(b)),
+                LenarValue::Void => f.write_str("Void"),
+                LenarValue::Bool(b) => f.write_str(&format!("{b}")),
+                LenarValue::Instance(i) => f.write_str(i.borrow().get_name()),
+                LenarValue::Function(func) => f.write_str(func.borrow().get_name()),
+                LenarValue::Enum(en) => f.write_str(&en.to_string()),
+                LenarValue::Ref(r) => f.write_str(&r.borrow().to_string()),
+            }
+        }
+    }
+
+    impl PartialEq for LenarValue {
+        fn eq(&self, other: &Self) -> bool {
+            if let Some(numbers) = NumberPair::coerce(self, other) {
+                return match numbers {
+                    NumberPair::Usize(l0, r0) => l0 == r0,
+                    NumberPair::Integer(l0, r0) => l0 == r0,
+                    NumberPair::Float(l0, r0) => l0 == r0,
+                };
+            }
+
+            match (self, other) {
+                (Self::Ref(l0), r0) => *l0.borrow() == *r0,
+                (l0, Self::Ref(r0)) => *l0 == *r0.borrow(),
+                (Self::List(l0), Self::List(r0)) => l0 == r0,
+                (Self::Map(l0), Self::Map(r0)) => l0 == r0,
+                (Self::Str(l0), r0) if r0.is_string() => r0.text_bytes() == Some(l0.as_bytes()),
+                (Self::Bytes(l0) | Self::OwnedBytes(l0), r0) if r0.is_string() => {
+                    r0.text_bytes() == Some(l0)
+                }
+                (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
+                (Self::Instance(_), Self::Instance(_)) => false,
+                (Self::Function(_), Self::Function(_)) => false,
+                (Self::Void, Self::Void) => true,
+                _ => false,
+            }
+        }
+    }
+
+    impl LenarValue {
+        /// Name of the type of the value, as shown in errors
+        pub fn type_name(&self) -> String {
+            let name = match self {
+                Self::Usize(_) | Self::Integer(_) => "integer",
+                Self::Float(_) => "float",
+                Self::List(_) => "list",
+                Self::Map(_) => "map",
+                Self::Str(_) | Self::Bytes(_) | Self::OwnedBytes(_) => "string",
+                Self::Byte(_) => "byte",
+                Self::Void => "void",
+                Self::Bool(_) => "bool",
+                Self::Instance(instance) => return instance.borrow().get_name().to_owned(),
+                Self::Function(_) => "function",
+                Self::Enum(_) => "enum",
+                Self::Ref(v) => return v.borrow().type_name(),
+            };
+            name.to_owned()
+        }
+
+        /// Error for an operation between two values where one isn't a number
+        fn number_mismatch(&self, other: &LenarValue) -> LenarError {
+            let actual = if self.is_number() { other } else { self };
+            LenarError::type_mismatch("number", actual)
+        }
+
+        pub fn is_void(&self) -> bool {
+            matches!(self, Self::Void)
+        }
+
+        pub fn as_bytes(&self) -> Option<&[u8]> {
+            match self {
+                Self::OwnedBytes(v) => Some(v),
+                Self::Bytes(v) => Some(v),
+                _ => None,
+            }
+        }
+
+        pub fn as_integer(&self) -> Option<usize> {
+            match self {
+                Self::Usize(v) => Some(*v),
+                Self::Integer(v) => usize::try_from(*v).ok(),
+                Self::Ref(v) => v.borrow().as_integer(),
+                _ => None,
+            }
+        }
+
+        pub fn as_signed_integer(&self) -> Option<i64> {
+            match self {
+                Self::Usize(v) => i64::try_from(*v).ok(),
+                Self::Integer(v) => Some(*v),
+                Self::Ref(v) => v.borrow().as_signed_integer(),
+                _ => None,
+            }
+        }
+
+        /// Get any number as a float
+        pub fn as_float(&self) -> Option<f64> {
+            match self {
+                Self::Usize(v) => Some(*v as f64),
+                Self::Integer(v) => Some(*v as f64),
+                Self::Float(v) => Some(*v),
+                Self::Ref(v) => v.borrow().as_float(),
+                _ => None,
+            }
+        }
+
+        /// Whether this is a `Str`, `Bytes` or `OwnedBytes`
+        pub fn is_string(&self) -> bool {
+            match self {
+                Self::Str(_) | Self::Bytes(_) | Self::OwnedBytes(_) => true,
+                Self::Ref(v) => v.borrow().is_string(),
+                _ => false,
+            }
+        }
+
+        /// Get the raw bytes of a `Str`, `Bytes` or `OwnedBytes`
+        fn text_bytes(&self) -> Option<&[u8]> {
+            match self {
+                Self::Str(v) => Some(v.as_bytes()),
+                Self::Bytes(v) | Self::OwnedBytes(v) => Some(v),
+                _ => None,
+            }
+        }
+
+        /// Get the text of a `Str`, `Bytes` or `OwnedBytes`
+        pub fn as_string(&self) -> Option<String> {
+            match self {
+                Self::Str(v) => Some(v.clone()),
+                Self::Bytes(v) | Self::OwnedBytes(v) => {
+                    Some(String::from_utf8_lossy(v).into_owned())
+                }
+                Self::Ref(v) => v.borrow().as_string(),
+                _ => None,
+            }
+        }
+
+        pub fn as_list(&self) -> Option<Vec<LenarValue>> {
+            match self {
+                Self::List(v) => Some(v.clone()),
+                Self::Ref(v) => v.borrow().as_list(),
+                _ => None,
+            }
+        }
+
+        pub fn as_map(&self) -> Option<BTreeMap<String, LenarValue>> {
+            match self {
+                Self::Map(v) => Some(v.clone()),
+                Self::Ref(v) => v.borrow().as_map(),
+                _ => None,
+            }
+        }
+
+        /// Get the item of a list at the given position, or the value of a map with the given key
+        pub fn get_index(&self, index: &LenarValue) -> LenarResult<LenarValue> {
+            match self {
+                Self::List(items) => {
+                    let index = index
+                        .as_integer()
+                        .ok_or_else(|| LenarError::type_mismatch("index", index))?;
+                    items
+                        .get(index)
+                        .cloned()
+                        .ok_or(LenarError::IndexOutOfRange {
+                            index,
+                            len: items.len(),
+                        })
+                }
+                Self::Map(entries) => {
+                    let key = index
+                        .as_string()
+                        .ok_or_else(|| LenarError::type_mismatch("string", index))?;
+                    Ok(entries.get(&key).cloned().unwrap_or(LenarValue::Void))
+                }
+                Self::Ref(v) => v.borrow().get_index(index),
+                _ => Err(LenarError::type_mismatch("list or map", self)),
+            }
+        }
+
+        /// Replace the item of a list at the given position, or the value of a map with the given key
+        pub fn set_index(&mut self, index: &LenarValue, value: LenarValue) -> LenarResult<()> {
+            match self {
+                Self::List(items) => {
+                    let index = index
+                        .as_integer()
+                        .ok_or_else(|| LenarError::type_mismatch("index", index))?;
+                    let len = items.len();
+                    let item = items
+                        .get_mut(index)
+                        .ok_or(LenarError::IndexOutOfRange { index, len })?;
+                    *item = value;
+                    Ok(())
+                }
+                Self::Map(entries) => {
+                    let key = index
+                        .as_string()
+                        .ok_or_else(|| LenarError::type_mismatch("string", index))?;
+                    entries.insert(key, value);
+                    Ok(())
+                }
+                Self::Ref(v) => v.borrow_mut().set_index(index, value),
+                _ => Err(LenarError::type_mismatch("list or map", self)),
+            }
+        }
+
+        /// Get the items of a list between `start`, included, and `end`, excluded
+        pub fn slice(&self, start: Option<usize>, end: Option<usize>) -> LenarResult<LenarValue> {
+            match self {
+                Self::List(items) => {
+                    let len = items.len();
+                    let end = end.unwrap_or(len);
+                    if end > len {
+                        return Err(LenarError::IndexOutOfRange { index: end, len });
+                    }
+                    let start = start.unwrap_or(0);
+                    if start > end {
+                        return Err(LenarError::IndexOutOfRange { index: start, len });
+                    }
+                    Ok(Self::List(items[start..end].to_vec()))
+                }
+                Self::Ref(v) => v.borrow().slice(start, end),
+                _ => Err(LenarError::type_mismatch("list", self)),
+            }
+        }
+
+        /// Replace the value held by a [`LenarValue::Ref`], other values are not affected
+        pub fn update_ref(&self, value: LenarValue) {
+            if let Self::Ref(reference) = self {
+                *reference.borrow_mut() = value;
+            }
+        }
+
+        pub fn as_bool(&self) -> Option<bool> {
+            match self {
+                Self::Bool(v) => Some(*v),
+                Self::Ref(v) => v.borrow().as_bool(),
+                _ => None,
+            }
+        }
+
+        /// Get the value behind any [`LenarValue::Ref`]
+        pub fn dereferenced(self) -> LenarValue {
+            match self {
+                Self::Ref(v) => v.borrow().clone().dereferenced(),
+                v => v,
+            }
+        }
+
+        fn arithmetic(
+            &self,
+            other: &LenarValue,
+            operation: &str,
+            apply: fn(NumberPair) -> Option<LenarValue>,
+        ) -> LenarResult<LenarValue> {
+            let numbers =
+                NumberPair::coerce(self, other).ok_or_else(|| self.number_mismatch(other))?;
+            apply(numbers).ok_or_else(|| LenarError::Overflow(operation.to_owned()))
+        }
+
+        fn division(
+            &self,
+            other: &LenarValue,
+            operation: &str,
+            apply: fn(NumberPair) -> Option<LenarValue>,
+        ) -> LenarResult<LenarValue> {
+            let numbers =
+                NumberPair::coerce(self, other).ok_or_else(|| self.number_mismatch(other))?;
+            if numbers.is_zero_divisor() {
+                return Err(LenarError::DivisionByZero);
+            }
+            apply(numbers).ok_or_else(|| LenarError::Overflow(operation.to_owned()))
+        }
+
+        pub fn checked_add(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.arithmetic(other, "add", NumberPair::add)
+        }
+
+        pub fn checked_sub(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.arithmetic(other, "sub", NumberPair::sub)
+        }
+
+        pub fn checked_mul(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.arithmetic(other, "mul", NumberPair::mul)
+        }
+
+        pub fn checked_div(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.division(other, "div", NumberPair::div)
+        }
+
+        pub fn checked_rem(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.division(other, "mod", NumberPair::rem)
+        }
+
+        pub fn checked_pow(&self, other: &LenarValue) -> LenarResult<LenarValue> {
+            self.arithmetic(other, "pow", NumberPair::pow)
+        }
+
+        pub fn checked_neg(&self) -> LenarResult<LenarValue> {
+            let overflow = || LenarError::Overflow("neg".to_owned());
+            match self {
+                Self::Usize(v) => i64::try_from(*v)
+                    .map(|v| LenarValue::Integer(-v))
+                    .map_err(|_| overflow()),
+                Self::Integer(v) => v
+                    .checked_neg()
+                    .map(LenarValue::Integer)
+                    .ok_or_else(overflow),
+                Self::Float(v) => Ok(LenarValue::Float(-v)),
+                Self::Ref(v) => v.borrow().checked_neg(),
+                _ => Err(LenarError::type_mismatch("number", self)),
+            }
+        }
+
+        /// Compare two numbers
+        pub fn compare(&self, other: &LenarValue) -> LenarResult<Ordering> {
+            match NumberPair::coerce(self, other) {
+                Some(NumberPair::Usize(a, b)) => Ok(a.cmp(&b)),
+                Some(NumberPair::Integer(a, b)) => Ok(a.cmp(&b)),
+                Some(NumberPair::Float(a, b)) => a
+                    .partial_cmp(&b)
+                    .ok_or(LenarError::Unordered { left: a, right: b }),
+                None => Err(self.number_mismatch(other)),
+            }
+        }
+
+        pub fn is_number(&self) -> bool {
+            match self {
+                Self::Usize(_) | Self::Integer(_) | Self::Float(_) => true,
+                Self::Ref(v) => v.borrow().is_number(),
+                _ => false,
+            }
+        }
+
+        pub fn as_func(&self) -> Option<Rc<RefCell<dyn RuntimeFunction>>> {
+            match self {
+                Self::Function(v) => Some(v.clone()),
+                Self::Ref(v) => v.borrow().as_func(),
+                _ => None,
+            }
+        }
+
+        pub fn set_integer(&mut self, integer: usize) {
+            match self {
+                Self::Usize(v) => *v = integer,
+                Self::Ref(r) => r.borrow_mut().set_integer(integer),
+                _ => {}
+            }
+        }
+    }
+
+    /// Two numbers coerced to a common type, so they can be operated together.
+    ///
+    /// A `Float` operand turns both into `Float`s, otherwise mixing an `Integer`
+    /// with a `Usize` turns both into `Integer`s.
+    #[derive(Debug, Clone, Copy)]
+    enum NumberPair {
+        Usize(usize, usize),
+        Integer(i64, i64),
+        Float(f64, f64),
+    }
+
+    impl NumberPair {
+        /// Floats results must stay finite
+        fn finite(n: f64) -> Option<LenarValue> {
+            n.is_finite().then_some(LenarValue::Float(n))
+        }
+
+        fn add(self) -> Option<LenarValue> {
+            match self {
+                Self::Usize(a, b) => a.checked_add(b).map(LenarValue::Usize),
+                Self::Integer(a, b) => a.checked_add(b).map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a + b),
+            }
+        }
+
+        fn sub(self) -> Option<LenarValue> {
+            match self {
+                // Going below zero turns the result into an `Integer`
+                Self::Usize(a, b) => a.checked_sub(b).map(LenarValue::Usize).or_else(|| {
+                    let (a, b) = (i64::try_from(a).ok()?, i64::try_from(b).ok()?);
+                    a.checked_sub(b).map(LenarValue::Integer)
+                }),
+                Self::Integer(a, b) => a.checked_sub(b).map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a - b),
+            }
+        }
+
+        fn mul(self) -> Option<LenarValue> {
+            match self {
+                Self::Usize(a, b) => a.checked_mul(b).map(LenarValue::Usize),
+                Self::Integer(a, b) => a.checked_mul(b).map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a * b),
+            }
+        }
+
+        fn div(self) -> Option<LenarValue> {
+            match self {
+                Self::Usize(a, b) => a.checked_div(b).map(LenarValue::Usize),
+                Self::Integer(a, b) => a.checked_div(b).map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a / b),
+            }
+        }
+
+        fn rem(self) -> Option<LenarValue> {
+            match self {
+                Self::Usize(a, b) => a.checked_rem(b).map(LenarValue::Usize),
+                Self::Integer(a, b) => a.checked_rem(b).map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a % b),
+            }
+        }
+
+        fn pow(self) -> Option<LenarValue> {
+            match self {
+                Self::Usize(a, b) => a.checked_pow(u32::try_from(b).ok()?).map(LenarValue::Usize),
+                // Negative exponents give fractions
+                Self::Integer(a, b) if b < 0 => Self::finite((a as f64).powf(b as f64)),
+                Self::Integer(a, b) => a
+                    .checked_pow(u32::try_from(b).ok()?)
+                    .map(LenarValue::Integer),
+                Self::Float(a, b) => Self::finite(a.powf(b)),
+            }
+        }
+
+        fn is_zero_divisor(&self) -> bool {
+            match self {
+                Self::Usize(_, b) => *b == 0,
+                Self::Integer(_, b) => *b == 0,
+                Self::Float(_, b) => *b == 0.0,
+            }
+        }
+
+        fn coerce(a: &LenarValue, b: &LenarValue) -> Option<Self> {
+            match (a, b) {
+                (LenarValue::Ref(a), b) => Self::coerce(&a.borrow(), b),
+                (a, LenarValue::Ref(b)) => Self::coerce(a, &b.borrow()),
+                (LenarValue::Usize(a), LenarValue::Usize(b)) => Some(Self::Usize(*a, *b)),
+                (LenarValue::Float(_), _) | (_, LenarValue::Float(_)) => {
+                    Some(Self::Float(a.as_float()?, b.as_float()?))
+                }
+                _ if a.is_number() && b.is_number() => {
+                    match (a.as_signed_integer(), b.as_signed_integer()) {
+                        (Some(a), Some(b)) => Some(Self::Integer(a, b)),
+                        // Too big for an `i64`
+                        _ => Some(Self::Float(a.as_float()?, b.as_float()?)),
+                    }
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Lenar special objects base
+    pub trait RuntimeInstance: Debug {
+        fn get_props(&self, path: &mut Iter<String>) -> LenarResult<LenarValue> {
+            let Some(prop) = path.next() else {
+                return Ok(LenarValue::Void);
+            };
+            let value = self.get_prop(prop);
+            if path.len() == 0 {
+                return Ok(value);
+            }
+
+            // Nested instances resolve the rest of the path
+            match value.dereferenced() {
+                LenarValue::Instance(instance) => instance.borrow().get_props(path),
+                value => Err(LenarError::type_mismatch("instance", &value)),
+            }
+        }
+
+        fn get_prop(&self, prop: &str) -> LenarValue;
+
+        /// Get the name and value of every field, for instances that are plain data.
+        /// Those are thrown as a map of their fields, the others as their name.
+        fn get_fields(&self) -> Option<Vec<(String, LenarValue)>> {
+            None
+        }
+
+        /// Replace the value of a property, properties are read-only by default
+        fn set_prop(&mut self, prop: &str, _value: LenarValue) -> LenarResult<()> {
+            Err(LenarError::PropertyNotAssignable {
+                holder: self.get_name().to_owned(),
+                property: prop.to_owned(),
+            })
+        }
+
+        fn get_name(&self) -> &str;
+    }
+
+    /// Lenar function base trait
+    pub trait RuntimeFunction: Debug {
+        /// Call the runtime function implementation
+        fn call(&mut self, _args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue>;
+
+        /// Get the function name
+        fn get_name(&self) -> &str;
+
+        /// Get the function kind
+        fn kind(&self) -> FunctionKind {
+            FunctionKind::Native
+        }
+
+        /// Get the number of arguments the function takes, when it's fixed. The
+        /// builtins that call it leave out the extra ones they give, such as indexes.
+        fn arity(&self) -> Option<usize> {
+            None
+        }
+
+        /// Get a copy of the function that can be called while the function is already
+        /// running, which recursive functions need. Otherwise, the function is exclusively
+        /// borrowed while it runs.
+        fn reentrant_handle(&self) -> Option<Box<dyn RuntimeFunction>> {
+            None
+        }
+    }
+
+    /// Objects whose evaluation can be nested unless [`Scope::set_max_evaluation_depth`]
+    /// says otherwise. Function calls, blocks and operators all count, so this bounds the
+    /// stack no matter how the code nests them. Each level takes up to around 6KB of
+    /// stack in debug builds, so this fits in the 2MB stack of spawned threads.
+    pub const DEFAULT_MAX_EVALUATION_DEPTH: usize = 250;
+
+    /// Numbers a `range()` can give, longer ones would exhaust the memory
+    pub const MAX_RANGE_LEN: usize = 1_000_000;
+
+    thread_local! {
+        /// Objects currently being evaluated
+        static EVALUATION_DEPTH: Cell<usize> = const { Cell::new(0) };
+        /// Objects whose evaluation can be nested in the code being run
+        static MAX_EVALUATION_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_EVALUATION_DEPTH) };
+    }
+
+    /// Run `f` with the given limit of nested evaluations, the previous one is
+    /// restored afterwards
+    fn with_max_evaluation_depth<T>(max_evaluation_depth: usize, f: impl FnOnce() -> T) -> T {
+        let previous = MAX_EVALUATION_DEPTH.replace(max_evaluation_depth);
+        let result = f();
+        MAX_EVALUATION_DEPTH.set(previous);
+        result
+    }
+
+    /// Call a [`RuntimeFunction`], recursively if it supports it. Otherwise calling it
+    /// while it runs fails with [`LenarError::AlreadyRunning`].
+    pub fn call_runtime_function(
+        function: &RefCell<dyn RuntimeFunction>,
+        args: Vec<LenarValue>,
+        parser: &Parser,
+    ) -> LenarResult<LenarValue> {
+        let handle = function
+            .try_borrow()
+            .map_err(|_| LenarError::AlreadyRunning)?
+            .reentrant_handle();
+        match handle {
+            Some(mut handle) => handle.call(args, parser),
+            None => function
+                .try_borrow_mut()
+                .map_err(|_| LenarError::AlreadyRunning)?
+                .call(args, parser),
+        }
+    }
+
+    /// Call a function given to a builtin, the arguments past its [`RuntimeFunction::arity`]
+    /// are left out
+    fn call_callback(
+        function: &RefCell<dyn RuntimeFunction>,
+        mut args: Vec<LenarValue>,
+        parser: &Parser,
+    ) -> LenarResult<LenarValue> {
+        let arity = function
+            .try_borrow()
+            .ok()
+            .and_then(|function| function.arity());
+        if let Some(arity) = arity {
+            args.truncate(arity);
+        }
+        call_runtime_function(function, args, parser)
+    }
+
+    /// Fail with [`LenarError::ArityMismatch`] if less than `expected` arguments were given
+    pub fn expect_arguments(
+        fn_name: &str,
+        args: &[LenarValue],
+        expected: usize,
+    ) -> LenarResult<()> {
+        if args.len() < expected {
+            return Err(LenarError::ArityMismatch {
+                fn_name: fn_name.to_owned(),
+                expected,
+                actual: args.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Variables of a scope, shared with the functions created in it so they also see
+    /// the ones defined after them
+    #[derive(Default)]
+    struct Frame {
+        variables: HashMap<String, Binding>,
+        /// Frame of the enclosing scope, only the globals have none
+        parent: Option<FrameRef>,
+        /// Names that can be seen from the parent frame, all of them when unset.
+        /// The rest are looked up in the globals
+        visible: Option<Rc<HashSet<String>>>,
+        /// Functions created in this frame, they hold it as long as they live
+        functions: Vec<Weak<RefCell<Function>>>,
+    }
+
+    type FrameRef = Rc<RefCell<Frame>>;
+
+    impl Debug for Frame {
+        // Functions hold the frame they are stored in
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Frame")
+                .field("variables", &self.variables.keys())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Frame {
+        /// Create the frame of a scope nested in `parent`
+        fn child_of(parent: &FrameRef) -> Self {
+            Self {
+                parent: Some(parent.clone()),
+                ..Self::default()
+            }
+        }
+
+        /// Find the binding of the closest variable with the given name
+        fn find(frame: &FrameRef, name: &str) -> Option<Binding> {
+            let mut frame = frame.clone();
+            loop {
+                let next = {
+                    let current = frame.borrow();
+                    if let Some(binding) = current.variables.get(name) {
+                        return Some(binding.clone());
+                    }
+                    let parent = current.parent.clone()?;
+                    match &current.visible {
+                        Some(visible) if !visible.contains(name) => Frame::globals(&parent),
+                        _ => parent,
+                    }
+                };
+                frame = next;
+            }
+        }
+
+        /// Get the frame of the globals, where every chain of frames ends
+        fn globals(frame: &FrameRef) -> FrameRef {
+            let mut frame = frame.clone();
+            loop {
+                let parent = frame.borrow().parent.clone();
+                match parent {
+                    Some(parent) => frame = parent,
+                    None => return frame,
+                }
+            }
+        }
+
+        /// Keep track of a function that holds this frame
+        fn add_function(&mut self, function: &Rc<RefCell<Function>>) {
+            if self.functions.len() == self.functions.capacity() {
+                self.functions
+                    .retain(|function| function.strong_count() > 0);
+            }
+            self.functions.push(Rc::downgrade(function));
+        }
+
+        /// Break the cycles between the frame of a finished scope and the functions created
+        /// in it, once nothing but the frame holds them
+        fn release(frame: &FrameRef) {
+            let Ok(current) = frame.try_borrow() else {
+                return;
+            };
+
+            let mut held = HashMap::new();
+            for binding in current.variables.values() {
+                // Shared bindings can be reached from somewhere else
+                if Rc::strong_count(binding) == 1 {
+                    count_functions(&binding.borrow(), &mut held);
+                }
+            }
+
+            let cyclic = current
+                .functions
+                .iter()
+                .filter_map(Weak::upgrade)
+                .filter(|function| {
+                    let held = held.get(&Rc::as_ptr(function).cast()).copied();
+                    // Running functions hold their definition too
+                    let idle = function
+                        .try_borrow()
+                        .is_ok_and(|function| Rc::strong_count(&function.definition) == 1);
+                    Some(Rc::strong_count(function) - 1) == held && idle
+                })
+                .count();
+            drop(current);
+
+            // Besides the scope itself
+            if Rc::strong_count(frame) == cyclic + 1 {
+                let variables = std::mem::take(&mut frame.borrow_mut().variables);
+                drop(variables);
+            }
+        }
+    }
+
+    /// Count how many times `value` holds each function, without looking into the
+    /// references shared with other values
+    fn count_functions(value: &LenarValue, held: &mut HashMap<*const (), usize>) {
+        match value {
+            LenarValue::Function(function) => {
+                *held.entry(Rc::as_ptr(function).cast()).or_default() += 1;
+            }
+            LenarValue::List(values) => {
+                for value in values {
+                    count_functions(value, held);
+                }
+            }
+            LenarValue::Map(values) => {
+                for value in values.values() {
+                    count_functions(value, held);
+                }
+            }
+            LenarValue::Enum(en) => {
+                for value in en.0.values() {
+                    count_functions(value, held);
+                }
+            }
+            LenarValue::Ref(reference) if Rc::strong_count(reference) == 1 => {
+                count_functions(&reference.borrow(), held);
+            }
+            _ => {}
+        }
+    }
+
+    /// Runtime Scope that includes variables and nested Scopes.
+    pub struct Scope {
+        frame: FrameRef,
+        scopes: HashMap<usize, Scope>,
+        /// Limit of nested evaluations, [`DEFAULT_MAX_EVALUATION_DEPTH`] when unset
+        max_evaluation_depth: Option<usize>,
+    }
+
+    impl Default for Scope {
+        fn default() -> Self {
+            let globals = Rc::new(RefCell::new(Frame::default()));
+            Self::with_frame(Frame::child_of(&globals))
+        }
+    }
+
+    impl Drop for Scope {
+        fn drop(&mut self) {
+            // Inner scopes hold this frame as their parent
+            self.scopes.clear();
+            Frame::release(&self.frame);
+        }
+    }
+
+    impl Scope {
+        fn with_frame(frame: Frame) -> Self {
+            Self {
+                frame: Rc::new(RefCell::new(frame)),
+                scopes: HashMap::default(),
+                max_evaluation_depth: None,
+            }
+        }
+
+        /// Limit the evaluations that can be nested while running code in this scope,
+        /// going past it fails with [`LenarError::ResourceLimitExceeded`]. Hosts that run
+        /// code in threads with a smaller stack should lower it, a bigger one allows raising it.
+        pub fn set_max_evaluation_depth(&mut self, max_evaluation_depth: usize) {
+            self.max_evaluation_depth = Some(max_evaluation_depth);
+        }
+
+        /// Add a [`RuntimeInstance`] to the global scope
+        pub fn add_global_instance(&mut self, val: impl RuntimeInstance + 'static) {
+            self.insert_global(
+                val.get_name().to_owned(),
+                LenarValue::Instance(Rc::new(RefCell::new(val))),
+            );
+        }
+
+        /// Add a [`RuntimeFunction`] to the global scope
+        pub fn add_global_function(&mut self, val: impl RuntimeFunction + 'static) {
+            self.insert_global(
+                val.get_name().to_owned(),
+                LenarValue::Function(Rc::new(RefCell::new(val))),
+            );
+        }
+
+        /// Define the global variables
+        pub fn setup_globals(&mut self) {
+            let resources_files = Rc::new(RefCell::new(Slab::<File>::new()));
+
+            /// Get a file opened by `openFile()` given its id
+            fn opened_file(resources_files: &Slab<File>, rid: usize) -> LenarResult<&File> {
+                resources_files.get(rid).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("unknown file id {rid}")).into()
+                })
+            }
+
+            #[derive(Debug)]
+            struct ToStringFunc {
+                resources_files: Rc<RefCell<Slab<File>>>,
+            }
+
+            impl ToStringFunc {
+                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
+                    Self { resources_files }
+                }
+            }
+
+            impl RuntimeFunction for ToStringFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("toString", &args, 1)?;
+                    match args[0] {
+                        LenarValue::Usize(rid) => {
+                            let resources_files = self.resources_files.borrow_mut();
+                            let mut file = opened_file(&resources_files, rid)?;
+                            let mut buf = Vec::new();
+                            file.read_to_end(&mut buf)?;
+                            Ok(LenarValue::OwnedBytes(buf))
+                        }
+                        _ => Ok(LenarValue::Void),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "toString"
+                }
+            }
+
+            #[derive(Debug)]
+            struct OpenFileFunc {
+                resources_files: Rc<RefCell<Slab<File>>>,
+            }
+
+            impl OpenFileFunc {
+                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
+                    Self { resources_files }
+                }
+            }
+
+            impl RuntimeFunction for OpenFileFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let file_path = string_arg(&args, 0)?;
+                    let file = File::open(file_path)?;
+
+                    let mut resources_files = self.resources_files.borrow_mut();
+                    let rid = resources_files.insert(file);
+
+                    Ok(LenarValue::Usize(rid))
+                }
+
+                fn get_name(&self) -> &str {
+                    "openFile"
+                }
+            }
+
+            #[derive(Debug)]
+            struct LenarGlobal;
+
+            impl RuntimeInstance for LenarGlobal {
+                fn get_prop(&self, prop: &str) -> LenarValue {
+                    match prop {
+                        "version" => LenarValue::Str("1.0.0".to_string()),
+                        _ => LenarValue::Void,
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "Lenar"
+                }
+            }
+
+            // `print()`
+            #[derive(Debug)]
+            struct PrintFunc;
+
+            impl PrintFunc {
+                pub fn write(value: &LenarValue) {
+                    match value {
+                        LenarValue::OwnedBytes(bts) => {
+                            stdout().write_all(bts).ok();
+                        }
+                        LenarValue::Byte(b) => {
+                            stdout().write_all(&[*b]).ok();
+                        }
+                        LenarValue::Bytes(bts) => {
+                            stdout().write_all(bts).ok();
+                        }
+                        LenarValue::Function(func) => {
+                            stdout().write_all(func.borrow().get_name().as_bytes()).ok();
+                        }
+                        LenarValue::Instance(instance) => {
+                            stdout()
+                                .write_all(instance.borrow().get_name().as_bytes())
+                                .ok();
+                        }
+                        LenarValue::Bool(b) => {
+                            stdout().write_all(b.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Usize(n) => {
+                            stdout().write_all(n.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Integer(n) => {
+                            stdout().write_all(n.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Float(n) => {
+                            stdout().write_all(n.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Str(s) => {
+                            stdout().write_all(s.as_bytes()).ok();
+                        }
+                        LenarValue::List(l) => {
+                            l.iter().for_each(Self::write);
+                        }
+                        LenarValue::Map(_) => {
+                            stdout().write_all(value.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Void => {
+                            stdout().write_all("Void".as_bytes()).ok();
+                        }
+                        LenarValue::Enum(en) => {
+                            stdout().write_all(en.to_string().as_bytes()).ok();
+                        }
+                        LenarValue::Ref(r) => {
+                            stdout().write_all(r.borrow().to_string().as_bytes()).ok();
+                        }
+                    }
+                }
+            }
+
+            impl RuntimeFunction for PrintFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    for val in args {
+                        Self::write(&val);
+                    }
+                    stdout().flush().ok();
+                    Ok(LenarValue::Void)
+                }
+
+                fn get_name(&self) -> &str {
+                    "print"
+                }
+            }
+
+            // println()
+            #[derive(Debug)]
+            struct PrintLnFunc;
+
+            impl RuntimeFunction for PrintLnFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    for val in args {
+                        PrintFunc::write(&val);
+                    }
+                    stdout().write_all("\n".as_bytes()).ok();
+                    stdout().flush().ok();
+                    Ok(LenarValue::Void)
+                }
+
+                fn get_name(&self) -> &str {
+                    "println"
+                }
+            }
+
+            // isEqual()
+            #[derive(Debug)]
+            struct IsEqual;
+
+            impl RuntimeFunction for IsEqual {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let args = args.first().zip(args.get(1));
+                    let res = if let Some((a, b)) = args {
+                        a.eq(b)
+                    } else {
+                        false
+                    };
+                    Ok(LenarValue::Bool(res))
+                }
+
+                fn get_name(&self) -> &str {
+                    "isEqual"
+                }
+            }
+
+            // NewList()
+            #[derive(Debug)]
+            struct NewListFunc;
+
+            impl RuntimeFunction for NewListFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    Ok(LenarValue::List(args))
+                }
+
+                fn get_name(&self) -> &str {
+                    "list"
+                }
+            }
+
+            fn map_arg(args: &[LenarValue]) -> LenarResult<BTreeMap<String, LenarValue>> {
+                let value = args.first().unwrap_or(&LenarValue::Void);
+                value
+                    .as_map()
+                    .ok_or_else(|| LenarError::type_mismatch("map", value))
+            }
+
+            fn key_arg(args: &[LenarValue]) -> LenarResult<String> {
+                string_arg(args, 1)
+            }
+
+            // get(map key)
+            #[derive(Debug)]
+            struct GetFunc;
+
+            impl RuntimeFunction for GetFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let mut map = map_arg(&args)?;
+                    let key = key_arg(&args)?;
+                    Ok(map.remove(&key).unwrap_or(LenarValue::Void))
+                }
+
+                fn get_name(&self) -> &str {
+                    "get"
+                }
+            }
+
+            // set(map key value)
+            #[derive(Debug)]
+            struct SetFunc;
+
+            impl RuntimeFunction for SetFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let mut map = map_arg(&args)?;
+                    let key = key_arg(&args)?;
+                    let value = args.get(2).cloned().unwrap_or(LenarValue::Void);
+                    map.insert(key, value);
+
+                    // Referenced maps are updated too
+                    let map = LenarValue::Map(map);
+                    args[0].update_ref(map.clone());
+                    Ok(map)
+                }
+
+                fn get_name(&self) -> &str {
+                    "set"
+                }
+            }
+
+            // has(map key)
+            #[derive(Debug)]
+            struct HasFunc;
+
+            impl RuntimeFunction for HasFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let map = map_arg(&args)?;
+                    let key = key_arg(&args)?;
+                    Ok(LenarValue::Bool(map.contains_key(&key)))
+                }
+
+                fn get_name(&self) -> &str {
+                    "has"
+                }
+            }
+
+            // keys(map)
+            #[derive(Debug)]
+            struct KeysFunc;
+
+            impl RuntimeFunction for KeysFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let map = map_arg(&args)?;
+                    Ok(LenarValue::List(
+                        map.into_keys().map(LenarValue::Str).collect(),
+                    ))
+                }
+
+                fn get_name(&self) -> &str {
+                    "keys"
+                }
+            }
+
+            // values(map)
+            #[derive(Debug)]
+            struct ValuesFunc;
+
+            impl RuntimeFunction for ValuesFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let map = map_arg(&args)?;
+                    Ok(LenarValue::List(map.into_values().collect()))
+                }
+
+                fn get_name(&self) -> &str {
+                    "values"
+                }
+            }
+
+            // remove(map key)
+            #[derive(Debug)]
+            struct RemoveFunc;
+
+            impl RuntimeFunction for RemoveFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let mut map = map_arg(&args)?;
+                    let key = key_arg(&args)?;
+                    map.remove(&key);
+
+                    // Referenced maps are updated too
+                    let map = LenarValue::Map(map);
+                    args[0].update_ref(map.clone());
+                    Ok(map)
+                }
+
+                fn get_name(&self) -> &str {
+                    "remove"
+                }
+            }
+
+            fn list_arg(args: &[LenarValue]) -> LenarResult<Vec<LenarValue>> {
+                let value = args.first().unwrap_or(&LenarValue::Void);
+                value
+                    .as_list()
+                    .ok_or_else(|| LenarError::type_mismatch("list", value))
+            }
+
+            fn function_arg(
+                args: &[LenarValue],
+                position: usize,
+            ) -> LenarResult<Rc<RefCell<dyn RuntimeFunction>>> {
+                let value = args.get(position).unwrap_or(&LenarValue::Void);
+                value
+                    .as_func()
+                    .ok_or_else(|| LenarError::type_mismatch("function", value))
+            }
+
+            // map(list fn), map(result fn), map(option fn)
+            fn map(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                if let Some(LenarValue::Enum(en)) =
+                    args.first().cloned().map(LenarValue::dereferenced)
+                {
+                    // Only the value of an `Ok` or a `Some` is mapped
+                    let fun = function_arg(&args, 1)?;
+                    for variant_name in ["Ok", "Some"] {
+                        if let Some(payload) = en.peek_variant(variant_name) {
+                            let payload =
+                                call_runtime_function(&fun, vec![payload.clone()], parser)?;
+                            return Ok(LenarValue::Enum(LenarEnum::new_with_variant(
+                                variant_name.to_owned(),
+                                payload,
+                            )));
+                        }
+                    }
+                    return Ok(LenarValue::Enum(en));
+                }
+
+                let items = list_arg(&args)?;
+                let fun = function_arg(&args, 1)?;
+                let mut mapped = Vec::with_capacity(items.len());
+                for (i, item) in items.into_iter().enumerate() {
+                    mapped.push(call_callback(
+                        &fun,
+                        vec![item, LenarValue::Usize(i)],
+                        parser,
+                    )?);
+                }
+                Ok(LenarValue::List(mapped))
+            }
+
+            // filter(list fn)
+            fn filter(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let fun = function_arg(&args, 1)?;
+                let mut filtered = Vec::new();
+                for (i, item) in items.into_iter().enumerate() {
+                    let keep =
+                        call_callback(&fun, vec![item.clone(), LenarValue::Usize(i)], parser)?;
+                    if as_bool(&keep)? {
+                        filtered.push(item);
+                    }
+                }
+                Ok(LenarValue::List(filtered))
+            }
+
+            // reduce(list fn initial?)
+            fn reduce(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let mut items = list_arg(&args)?.into_iter();
+                let fun = function_arg(&args, 1)?;
+                // Without an initial value the first item is used
+                let mut accumulator = match args.get(2) {
+                    Some(initial) => initial.clone(),
+                    None => items.next().unwrap_or(LenarValue::Void),
+                };
+                for item in items {
+                    accumulator = call_runtime_function(&fun, vec![accumulator, item], parser)?;
+                }
+                Ok(accumulator)
+            }
+
+            // find(list fn)
+            fn find(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let fun = function_arg(&args, 1)?;
+                for (i, item) in items.into_iter().enumerate() {
+                    let found =
+                        call_callback(&fun, vec![item.clone(), LenarValue::Usize(i)], parser)?;
+                    if as_bool(&found)? {
+                        return Ok(item);
+                    }
+                }
+                Ok(LenarValue::Void)
+            }
+
+            // any(list fn)
+            fn any(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let fun = function_arg(&args, 1)?;
+                for (i, item) in items.into_iter().enumerate() {
+                    let matches = call_callback(&fun, vec![item, LenarValue::Usize(i)], parser)?;
+                    if as_bool(&matches)? {
+                        return Ok(LenarValue::Bool(true));
+                    }
+                }
+                Ok(LenarValue::Bool(false))
+            }
+
+            // all(list fn)
+            fn all(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let fun = function_arg(&args, 1)?;
+                for (i, item) in items.into_iter().enumerate() {
+                    let matches = call_callback(&fun, vec![item, LenarValue::Usize(i)], parser)?;
+                    if !as_bool(&matches)? {
+                        return Ok(LenarValue::Bool(false));
+                    }
+                }
+                Ok(LenarValue::Bool(true))
+            }
+
+            // len(list), len(string)
+            fn len(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let value = args.first().cloned().unwrap_or(LenarValue::Void);
+                match value.dereferenced() {
+                    LenarValue::List(items) => Ok(LenarValue::Usize(items.len())),
+                    LenarValue::Map(entries) => Ok(LenarValue::Usize(entries.len())),
+                    value if value.is_string() => {
+                        Ok(LenarValue::Usize(string_arg(&[value], 0)?.chars().count()))
+                    }
+                    value => Err(LenarError::type_mismatch("list, string or map", &value)),
+                }
+            }
+
+            // push(list value)
+            fn push(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let mut items = list_arg(&args)?;
+                items.extend(args.iter().skip(1).cloned());
+
+                // Referenced lists are updated too
+                let items = LenarValue::List(items);
+                args[0].update_ref(items.clone());
+                Ok(items)
+            }
+
+            // pop(list)
+            fn pop(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let mut items = list_arg(&args)?;
+                let last = items.pop().unwrap_or(LenarValue::Void);
+
+                // Referenced lists lose their last item
+                args[0].update_ref(LenarValue::List(items));
+                Ok(last)
+            }
+
+            // concat(list list ...), concat(string string ...)
+            fn concat(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                if args.first().is_some_and(LenarValue::is_string) {
+                    let mut concatenated = String::new();
+                    for position in 0..args.len() {
+                        concatenated.push_str(&string_arg(&args, position)?);
+                    }
+                    return Ok(LenarValue::Str(concatenated));
+                }
+
+                let mut concatenated = Vec::new();
+                for value in &args {
+                    let items = value
+                        .as_list()
+                        .ok_or_else(|| LenarError::type_mismatch("list", value))?;
+                    concatenated.extend(items);
+                }
+                Ok(LenarValue::List(concatenated))
+            }
+
+            // reverse(list)
+            fn reverse(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let mut items = list_arg(&args)?;
+                items.reverse();
+                Ok(LenarValue::List(items))
+            }
+
+            /// Stable merge sort that stops at the first comparison that fails. Comparators
+            /// written in lenar can be inconsistent, which `slice::sort_by` may panic on.
+            fn merge_sort(
+                mut items: Vec<LenarValue>,
+                compare: &mut impl FnMut(&LenarValue, &LenarValue) -> LenarResult<Ordering>,
+            ) -> LenarResult<Vec<LenarValue>> {
+                if items.len() <= 1 {
+                    return Ok(items);
+                }
+                let right = items.split_off(items.len() / 2);
+                let left = merge_sort(items, compare)?;
+                let right = merge_sort(right, compare)?;
+
+                let mut merged = Vec::with_capacity(left.len() + right.len());
+                let mut left = left.into_iter().peekable();
+                let mut right = right.into_iter().peekable();
+                while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+                    // Equal items keep their order
+                    let next = if compare(a, b)? == Ordering::Greater {
+                        right.next()
+                    } else {
+                        left.next()
+                    };
+                    merged.extend(next);
+                }
+                merged.extend(left);
+                merged.extend(right);
+                Ok(merged)
+            }
+
+            // sort(list comparator?)
+            fn sort(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let comparator = args.get(1).map(|_| function_arg(&args, 1)).transpose()?;
+
+                let items = merge_sort(items, &mut |a, b| match &comparator {
+                    // Comparators return a negative number, zero or a positive number
+                    Some(comparator) => {
+                        call_runtime_function(comparator, vec![a.clone(), b.clone()], parser)?
+                            .compare(&LenarValue::Usize(0))
+                    }
+                    None => match (a.as_string(), b.as_string()) {
+                        (Some(a), Some(b)) => Ok(a.cmp(&b)),
+                        _ => a.compare(b),
+                    },
+                })?;
+                Ok(LenarValue::List(items))
+            }
+
+            // zip(list list)
+            fn zip(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let others = list_arg(&args[1..])?;
+                Ok(LenarValue::List(
+                    items
+                        .into_iter()
+                        .zip(others)
+                        .map(|(item, other)| LenarValue::List(vec![item, other]))
+                        .collect(),
+                ))
+            }
+
+            // enumerate(list)
+            fn enumerate(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                Ok(LenarValue::List(
+                    items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, item)| LenarValue::List(vec![LenarValue::Usize(i), item]))
+                        .collect(),
+                ))
+            }
+
+            // range(start end step?)
+            fn range(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                expect_arguments("range", &args, 2)?;
+                let (start, end) = (&args[0], &args[1]);
+                let step = args.get(2).cloned().unwrap_or(LenarValue::Usize(1));
+
+                // Positive steps count up to the end, negative ones count down to it
+                let direction = match step.compare(&LenarValue::Usize(0))? {
+                    Ordering::Greater => Ordering::Less,
+                    Ordering::Less => Ordering::Greater,
+                    Ordering::Equal => return Err(LenarError::WrongValue("step".to_owned())),
+                };
+
+                let mut numbers = Vec::new();
+                let mut current = start.clone().dereferenced();
+                while current.compare(end)? == direction {
+                    if numbers.len() == MAX_RANGE_LEN {
+                        return Err(LenarError::ResourceLimitExceeded {
+                            resource: "numbers in a range".to_owned(),
+                            limit: MAX_RANGE_LEN,
+                        });
+                    }
+                    let next = current.checked_add(&step);
+                    numbers.push(current);
+                    match next {
+                        Ok(next) => current = next,
+                        // There is nothing past the largest number
+                        Err(LenarError::Overflow(_)) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(LenarValue::List(numbers))
+            }
+
+            fn string_arg(args: &[LenarValue], position: usize) -> LenarResult<String> {
+                let value = args.get(position).unwrap_or(&LenarValue::Void);
+                value
+                    .as_string()
+                    .ok_or_else(|| LenarError::type_mismatch("string", value))
+            }
+
+            // split(string separator)
+            fn split(args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let separator = string_arg(&args, 1)?;
+
+                // An empty separator splits every character
+                if separator.is_empty() {
+                    return chars(args, parser);
+                }
+
+                Ok(LenarValue::List(
+                    text.split(&separator)
+                        .map(|part| LenarValue::Str(part.to_owned()))
+                        .collect(),
+                ))
+            }
+
+            // join(list separator?)
+            fn join(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let items = list_arg(&args)?;
+                let separator = match args.get(1) {
+                    Some(_) => string_arg(&args, 1)?,
+                    None => String::new(),
+                };
+                let parts = items
+                    .iter()
+                    .map(|item| item.as_string().unwrap_or_else(|| item.to_string()))
+                    .collect::<Vec<_>>();
+                Ok(LenarValue::Str(parts.join(&separator)))
+            }
+
+            // trim(string)
+            fn trim(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                Ok(LenarValue::Str(text.trim().to_owned()))
+            }
+
+            // replace(string from to)
+            fn replace(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let from = string_arg(&args, 1)?;
+                let to = string_arg(&args, 2)?;
+                Ok(LenarValue::Str(text.replace(&from, &to)))
+            }
+
+            // contains(string pattern)
+            fn contains(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let pattern = string_arg(&args, 1)?;
+                Ok(LenarValue::Bool(text.contains(&pattern)))
+            }
+
+            // startsWith(string prefix)
+            fn starts_with(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let prefix = string_arg(&args, 1)?;
+                Ok(LenarValue::Bool(text.starts_with(&prefix)))
+            }
+
+            // endsWith(string suffix)
+            fn ends_with(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let suffix = string_arg(&args, 1)?;
+                Ok(LenarValue::Bool(text.ends_with(&suffix)))
+            }
+
+            // toUpper(string)
+            fn to_upper(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                Ok(LenarValue::Str(text.to_uppercase()))
+            }
+
+            // toLower(string)
+            fn to_lower(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                Ok(LenarValue::Str(text.to_lowercase()))
+            }
+
+            // substring(string start end?)
+            fn substring(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let position = |position: usize| {
+                    args.get(position)
+                        .map(|value| {
+                            value
+                                .as_integer()
+                                .ok_or_else(|| LenarError::type_mismatch("index", value))
+                        })
+                        .transpose()
+                };
+
+                // Positions count characters, not bytes
+                let len = text.chars().count();
+                let end = position(2)?.unwrap_or(len);
+                if end > len {
+                    return Err(LenarError::IndexOutOfRange { index: end, len });
+                }
+                let start = position(1)?.unwrap_or(0);
+                if start > end {
+                    return Err(LenarError::IndexOutOfRange { index: start, len });
+                }
+                Ok(LenarValue::Str(
+                    text.chars().skip(start).take(end - start).collect(),
+                ))
+            }
+
+            // chars(string)
+            fn chars(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                Ok(LenarValue::List(
+                    text.chars()
+                        .map(|c| LenarValue::Str(c.to_string()))
+                        .collect(),
+                ))
+            }
+
+            // parseInt(string)
+            fn parse_int(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                let text = text.trim();
+                text.parse::<usize>()
+                    .map(LenarValue::Usize)
+                    .or_else(|_| text.parse::<i64>().map(LenarValue::Integer))
+                    .map_err(|_| LenarError::WrongValue("integer".to_owned()))
+            }
+
+            // parseFloat(string)
+            fn parse_float(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let text = string_arg(&args, 0)?;
+                text.trim()
+                    .parse::<f64>()
+                    .map(LenarValue::Float)
+                    .map_err(|_| LenarError::WrongValue("float".to_owned()))
+            }
+
+            // formatNumber(number decimals?)
+            fn format_number(args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+                let number = args
+                    .first()
+                    .cloned()
+                    .unwrap_or(LenarValue::Void)
+                    .dereferenced();
+                if !number.is_number() {
+                    return Err(LenarError::type_mismatch("number", &number));
+                }
+                let formatted = match args.get(1) {
+                    Some(decimals) => {
+                        let decimals = decimals
+                            .as_integer()
+                            .ok_or_else(|| LenarError::type_mismatch("integer", decimals))?;
+                        // Floats have no meaningful digits that far, huge precisions exhaust memory
+                        if decimals > 100 {
+                            return Err(LenarError::WrongValue("at most 100 decimals".to_owned()));
+                        }
+                        match number {
+                            // Integers are exact, going through a float could round them
+                            LenarValue::Usize(_) | LenarValue::Integer(_) if decimals > 0 => {
+                                format!("{number}.{}", "0".repeat(decimals))
+                            }
+                            LenarValue::Usize(_) | LenarValue::Integer(_) => number.to_string(),
+                            _ => format!("{:.*}", decimals, number.as_float().unwrap_or_default()),
+                        }
+                    }
+                    None => number.to_string(),
+                };
+                Ok(LenarValue::Str(formatted))
+            }
+
+            // Builtins backed by a plain function, see the `list_functions` and
+            // `string_functions` tables below
+            #[derive(Debug, Clone)]
+            struct NativeFunc {
+                name: &'static str,
+                operation: fn(Vec<LenarValue>, &Parser) -> LenarResult<LenarValue>,
+            }
+
+            impl RuntimeFunction for NativeFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    (self.operation)(args, parser)
+                }
+
+                fn get_name(&self) -> &str {
+                    self.name
+                }
+
+                // Some of them call functions that can use them again
+                fn reentrant_handle(&self) -> Option<Box<dyn RuntimeFunction>> {
+                    Some(Box::new(self.clone()))
+                }
+            }
+
+            // iter()
+            #[derive(Debug, Clone)]
+            struct IterFunc {
+                resources_files: Rc<RefCell<Slab<File>>>,
+            }
+
+            impl IterFunc {
+                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
+                    Self { resources_files }
+                }
+            }
+
+            impl RuntimeFunction for IterFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("iter", &args, 2)?;
+                    let iterator = args.remove(0);
+                    let fun = args.remove(0);
+
+                    if let LenarValue::Function(fun) = fun {
+                        match iterator {
+                            LenarValue::Usize(rid) => {
+                                // The files stay available to the function
+                                let file = opened_file(&self.resources_files.borrow(), rid)?
+                                    .try_clone()?;
+                                let bytes = BufReader::new(file).bytes();
+
+                                for byte in bytes {
+                                    if let Ok(byte) = byte {
+                                        call_runtime_function(
+                                            &fun,
+                                            vec![LenarValue::Byte(byte)],
+                                            _parser,
+                                        )?;
+                                    } else {
+                                        break;
+                                    }
+                                }
+                            }
+                            LenarValue::Bytes(bytes) => {
+                                for byte in bytes {
+                                    call_runtime_function(
+                                        &fun,
+                                        vec![LenarValue::Byte(byte)],
+                                        _parser,
+                                    )?;
+                                }
+                            }
+                            LenarValue::OwnedBytes(bytes) => {
+                                for byte in bytes {
+                                    call_runtime_function(
+                                        &fun,
+                                        vec![LenarValue::Byte(byte)],
+                                        _parser,
+                                    )?;
+                                }
+                            }
+                            LenarValue::Str(text) => {
+                                for c in text.chars() {
+                                    call_runtime_function(
+                                        &fun,
+                                        vec![LenarValue::Str(c.to_string())],
+                                        _parser,
+                                    )?;
+                                }
+                            }
+                            LenarValue::List(items) => {
+                                for (i, item) in items.into_iter().enumerate() {
+                                    call_callback(&fun, vec![item, LenarValue::Usize(i)], _parser)?;
+                                }
+                            }
+                            LenarValue::Map(entries) => {
+                                for (key, value) in entries {
+                                    call_callback(
+                                        &fun,
+                                        vec![value, LenarValue::Str(key)],
+                                        _parser,
+                                    )?;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    Ok(LenarValue::Void)
+                }
+
+                fn get_name(&self) -> &str {
+                    "iter"
+                }
+
+                fn reentrant_handle(&self) -> Option<Box<dyn RuntimeFunction>> {
+                    Some(Box::new(self.clone()))
+                }
+            }
+
+            // sleep()
+            #[derive(Debug)]
+            struct SleepFunc;
+
+            impl RuntimeFunction for SleepFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("sleep", &args, 1)?;
+                    let v = args.remove(0);
+                    let time = v
+                        .as_float()
+                        .map(|time| Duration::try_from_secs_f64(time / 1000.0));
+                    if let Some(Ok(time)) = time {
+                        thread::sleep(time);
+                    }
+                    Ok(LenarValue::Void)
+                }
+
+                fn get_name(&self) -> &str {
+                    "sleep"
+                }
+            }
+
+            // Ok()
+            #[derive(Debug)]
+            struct OkFunc;
+
+            impl RuntimeFunction for OkFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("Ok", &args, 1)?;
+                    let v = args.remove(0);
+                    Ok(LenarValue::Enum(LenarEnum::new_with_variant(
+                        "Ok".to_string(),
+                        v,
+                    )))
+                }
+
+                fn get_name(&self) -> &str {
+                    "Ok"
+                }
+            }
+
+            // Err()
+            #[derive(Debug)]
+            struct ErrFunc;
+
+            impl RuntimeFunction for ErrFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("Err", &args, 1)?;
+                    let v = args.remove(0);
+                    Ok(LenarValue::Enum(LenarEnum::new_with_variant(
+                        "Err".to_string(),
+                        v,
+                    )))
+                }
+
+                fn get_name(&self) -> &str {
+                    "Err"
+                }
+            }
+
+            // isOk()
+            #[derive(Debug)]
+            struct IsOkFunc;
+
+            impl RuntimeFunction for IsOkFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("isOk", &args, 1)?;
+                    let v = args.remove(0);
+                    match v.dereferenced() {
+                        LenarValue::Enum(variants) => {
+                            let ok_variant = variants.peek_variant("Ok");
+                            Ok(LenarValue::Bool(ok_variant.is_some()))
+                        }
+                        v => Err(LenarError::type_mismatch("Ok or Err", &v)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "isOk"
+                }
+            }
+
+            // unwrap()
+            #[derive(Debug)]
+            struct UnwrapFunc;
+
+            impl RuntimeFunction for UnwrapFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("unwrap", &args, 1)?;
+                    let value = args.remove(0).dereferenced();
+                    let LenarValue::Enum(variants) = &value else {
+                        return Err(LenarError::type_mismatch("Ok or Some", &value));
+                    };
+                    match variants.peek_success() {
+                        Some(payload) => Ok(payload.clone()),
+                        None if variants.is_failure() => {
+                            Err(LenarError::WrongValue("Ok or Some".to_owned()))
+                        }
+                        None => Err(LenarError::type_mismatch("Ok or Some", &value)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "unwrap"
+                }
+            }
+
+            // unwrapErr()
+            #[derive(Debug)]
+            struct UnwrapErrFunc;
+
+            impl RuntimeFunction for UnwrapErrFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("unwrapErr", &args, 1)?;
+                    let value = args.remove(0).dereferenced();
+                    let LenarValue::Enum(variants) = &value else {
+                        return Err(LenarError::type_mismatch("Ok or Err", &value));
+                    };
+                    match variants.peek_variant("Err") {
+                        Some(payload) => Ok(payload.clone()),
+                        None if variants.peek_variant("Ok").is_some() => {
+                            Err(LenarError::WrongValue("Err".to_owned()))
+                        }
+                        None => Err(LenarError::type_mismatch("Ok or Err", &value)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "unwrapErr"
+                }
+            }
+
+            // Some()
+            #[derive(Debug)]
+            struct SomeFunc;
+
+            impl RuntimeFunction for SomeFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("Some", &args, 1)?;
+                    let v = args.into_iter().next().unwrap();
+                    Ok(LenarValue::Enum(LenarEnum::new_with_variant(
+                        "Some".to_string(),
+                        v,
+                    )))
+                }
+
+                fn get_name(&self) -> &str {
+                    "Some"
+                }
+            }
+
+            // isSome(), isNone()
+            #[derive(Debug)]
+            struct IsVariantFunc {
+                name: &'static str,
+                variant_name: &'static str,
+            }
+
+            impl RuntimeFunction for IsVariantFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments(self.name, &args, 1)?;
+                    match args[0].clone().dereferenced() {
+                        LenarValue::Enum(variants) => Ok(LenarValue::Bool(
+                            variants.peek_variant(self.variant_name).is_some(),
+                        )),
+                        v => Err(LenarError::type_mismatch("Some or None", &v)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    self.name
+                }
+            }
+
+            // unwrapOr(value default)
+            #[derive(Debug)]
+            struct UnwrapOrFunc;
+
+            impl RuntimeFunction for UnwrapOrFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("unwrapOr", &args, 2)?;
+                    match args[0].clone().dereferenced() {
+                        LenarValue::Enum(variants) => Ok(variants
+                            .peek_success()
+                            .cloned()
+                            .unwrap_or_else(|| args[1].clone())),
+                        v => Err(LenarError::type_mismatch("Ok or Some", &v)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "unwrapOr"
+                }
+            }
+
+            // andThen(value fn)
+            #[derive(Debug, Clone)]
+            struct AndThenFunc;
+
+            impl RuntimeFunction for AndThenFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    let fun = function_arg(&args, 1)?;
+                    match args[0].clone().dereferenced() {
+                        // The function gives the next `Ok`/`Err` or `Some`/`None`
+                        LenarValue::Enum(variants) => match variants.peek_success() {
+                            Some(payload) => {
+                                call_runtime_function(&fun, vec![payload.clone()], parser)
+                            }
+                            None => Ok(LenarValue::Enum(variants)),
+                        },
+                        value => Err(LenarError::type_mismatch("Ok or Some", &value)),
+                    }
+                }
+
+                fn get_name(&self) -> &str {
+                    "andThen"
+                }
+
+                fn reentrant_handle(&self) -> Option<Box<dyn RuntimeFunction>> {
+                    Some(Box::new(self.clone()))
+                }
+            }
+
+            // throw(value)
+            #[derive(Debug)]
+            struct ThrowFunc;
+
+            impl RuntimeFunction for ThrowFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("throw", &args, 1)?;
+                    Err(LenarError::UserThrown(ThrownValue::from(&args[0])))
+                }
+
+                fn get_name(&self) -> &str {
+                    "throw"
+                }
+            }
+
+            // ref()
+            #[derive(Debug)]
+            struct RefFunc;
+
+            impl RuntimeFunction for RefFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("ref", &args, 1)?;
+                    let v = args.remove(0);
+                    Ok(LenarValue::Ref(Rc::new(RefCell::new(v))))
+                }
+
+                fn get_name(&self) -> &str {
+                    "ref"
+                }
+            }
+
+            // add(), sub(), mul(), div(), mod(), pow()
+            #[derive(Debug)]
+            struct ArithmeticFunc {
+                name: &'static str,
+                operation: fn(&LenarValue, &LenarValue) -> LenarResult<LenarValue>,
+            }
+
+            impl RuntimeFunction for ArithmeticFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments(self.name, &args, 2)?;
+                    let (value, operand) = (&args[0], &args[1]);
+
+                    let result = (self.operation)(value, operand)?;
+
+                    // Referenced values are updated with the result
+                    value.update_ref(result.clone());
+
+                    Ok(result)
+                }
+
+                fn get_name(&self) -> &str {
+                    self.name
+                }
+            }
+
+            // lt(), lte(), gt(), gte()
+            #[derive(Debug)]
+            struct ComparisonFunc {
+                name: &'static str,
+                expected: fn(Ordering) -> bool,
+            }
+
+            impl RuntimeFunction for ComparisonFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments(self.name, &args, 2)?;
+                    let (value, other) = (&args[0], &args[1]);
+
+                    let ordering = value.compare(other)?;
+                    Ok(LenarValue::Bool((self.expected)(ordering)))
+                }
+
+                fn get_name(&self) -> &str {
+                    self.name
+                }
+            }
+
+            // min(), max()
+            #[derive(Debug)]
+            struct ExtremumFunc {
+                name: &'static str,
+                /// How a value must compare to the current extremum to replace it
+                replaces_when: Ordering,
+            }
+
+            impl RuntimeFunction for ExtremumFunc {
+                fn call(
+                    &mut self,
+                    args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments(self.name, &args, 1)?;
+                    let mut args = args.into_iter();
+                    let mut extremum = args.next().unwrap();
+
+                    for value in args {
+                        if value.compare(&extremum)? == self.replaces_when {
+                            extremum = value;
+                        }
+                    }
+
+                    Ok(extremum.dereferenced())
+                }
+
+                fn get_name(&self) -> &str {
+                    self.name
+                }
+            }
+
+            // not()
+            #[derive(Debug)]
+            struct NotFunc;
+
+            impl RuntimeFunction for NotFunc {
+                fn call(
+                    &mut self,
+                    mut args: Vec<LenarValue>,
+                    _parser: &Parser,
+                ) -> LenarResult<LenarValue> {
+                    expect_arguments("not", &args, 1)?;
+                    let cond = args.remove(0);
+                    Ok(LenarValue::Bool(!as_bool(&cond)?))
+                }
+
+                fn get_name(&self) -> &str {
+                    "not"
+                }
+            }
+
+            self.insert_global(
+                "not".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(NotFunc))),
+            );
+            let arithmetic_functions: [(_, fn(&_, &_) -> _); 6] = [
+                ("add", LenarValue::checked_add),
+                ("sub", LenarValue::checked_sub),
+                ("mul", LenarValue::checked_mul),
+                ("div", LenarValue::checked_div),
+                ("mod", LenarValue::checked_rem),
+                ("pow", LenarValue::checked_pow),
+            ];
+            for (name, operation) in arithmetic_functions {
+                self.insert_global(
+                    name.to_string(),
+                    LenarValue::Function(Rc::new(RefCell::new(ArithmeticFunc { name, operation }))),
+                );
+            }
+            let comparison_functions: [(_, fn(_) -> _); 4] = [
+                ("lt", Ordering::is_lt),
+                ("lte", Ordering::is_le),
+                ("gt", Ordering::is_gt),
+                ("gte", Ordering::is_ge),
+            ];
+            for (name, expected) in comparison_functions {
+                self.insert_global(
+                    name.to_string(),
+                    LenarValue::Function(Rc::new(RefCell::new(ComparisonFunc { name, expected }))),
+                );
+            }
+            let list_functions: [(_, fn(_, &_) -> _); 15] = [
+                ("map", map),
+                ("filter", filter),
+                ("reduce", reduce),
+                ("find", find),
+                ("any", any),
+                ("all", all),
+                ("len", len),
+                ("push", push),
+                ("pop", pop),
+                ("concat", concat),
+                ("reverse", reverse),
+                ("sort", sort),
+                ("zip", zip),
+                ("enumerate", enumerate),
+                ("range", range),
+            ];
+            for (name, operation) in list_functions {
+                self.insert_global(
+                    name.to_string(),
+                    LenarValue::Function(Rc::new(RefCell::new(NativeFunc { name, operation }))),
+                );
+            }
+            let string_functions: [(_, fn(_, &_) -> _); 14] = [
+                ("split", split),
+                ("join", join),
+                ("trim", trim),
+                ("replace", replace),
+                ("contains", contains),
+                ("startsWith", starts_with),
+                ("endsWith", ends_with),
+                ("toUpper", to_upper),
+                ("toLower", to_lower),
+                ("substring", substring),
+                ("chars", chars),
+                ("parseInt", parse_int),
+                ("parseFloat", parse_float),
+                ("formatNumber", format_number),
+            ];
+            for (name, operation) in string_functions {
+                self.insert_global(
+                    name.to_string(),
+                    LenarValue::Function(Rc::new(RefCell::new(NativeFunc { name, operation }))),
+                );
+            }
+            self.insert_global(
+                "min".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ExtremumFunc {
+                    name: "min",
+                    replaces_when: Ordering::Less,
+                }))),
+            );
+            self.insert_global(
+                "max".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ExtremumFunc {
+                    name: "max",
+                    replaces_when: Ordering::Greater,
+                }))),
+            );
+            self.insert_global(
+                "ref".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(RefFunc))),
+            );
+            self.insert_global(
+                "Some".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(SomeFunc))),
+            );
+            self.insert_global(
+                "None".to_string(),
+                LenarValue::Enum(LenarEnum::new_with_variant(
+                    "None".to_string(),
+                    LenarValue::Void,
+                )),
+            );
+            for (name, variant_name) in [("isSome", "Some"), ("isNone", "None")] {
+                self.insert_global(
+                    name.to_string(),
+                    LenarValue::Function(Rc::new(RefCell::new(IsVariantFunc {
+                        name,
+                        variant_name,
+                    }))),
+                );
+            }
+            self.insert_global(
+                "throw".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ThrowFunc))),
+            );
+            self.insert_global(
+                "unwrapOr".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(UnwrapOrFunc))),
+            );
+            self.insert_global(
+                "andThen".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(AndThenFunc))),
+            );
+            self.insert_global(
+                "unwrapErr".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(UnwrapErrFunc))),
+            );
+            self.insert_global(
+                "unwrap".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(UnwrapFunc))),
+            );
+            self.insert_global(
+                "Err".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ErrFunc))),
+            );
+            self.insert_global(
+                "isOk".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(IsOkFunc))),
+            );
+            self.insert_global(
+                "Ok".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(OkFunc))),
+            );
+            self.insert_global(
+                "sleep".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(SleepFunc))),
+            );
+            self.insert_global(
+                "list".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(NewListFunc))),
+            );
+            self.insert_global(
+                "get".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(GetFunc))),
+            );
+            self.insert_global(
+                "set".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(SetFunc))),
+            );
+            self.insert_global(
+                "has".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(HasFunc))),
+            );
+            self.insert_global(
+                "keys".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(KeysFunc))),
+            );
+            self.insert_global(
+                "values".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ValuesFunc))),
+            );
+            self.insert_global(
+                "remove".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(RemoveFunc))),
+            );
+            self.insert_global(
+                "iter".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(IterFunc::new(
+                    resources_files.clone(),
+                )))),
+            );
+            self.insert_global(
+                "toString".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(ToStringFunc::new(
+                    resources_files.clone(),
+                )))),
+            );
+            self.insert_global(
+                "openFile".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(OpenFileFunc::new(resources_files)))),
+            );
+            self.insert_global(
+                "print".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(PrintFunc))),
+            );
+            self.insert_global(
+                "println".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(PrintLnFunc))),
+            );
+            self.insert_global(
+                "Lenar".to_string(),
+                LenarValue::Instance(Rc::new(RefCell::new(LenarGlobal))),
+            );
+            self.insert_global(
+                "isEqual".to_string(),
+                LenarValue::Function(Rc::new(RefCell::new(IsEqual))),
+            );
+        }
+
+        fn insert_variable(&mut self, name: String, value: LenarValue) {
+            self.frame
+                .borrow_mut()
+                .variables
+                .insert(name, Rc::new(RefCell::new(value)));
+        }
+
+        fn insert_global(&mut self, name: String, value: LenarValue) {
+            Frame::globals(&self.frame)
+                .borrow_mut()
+                .variables
+                .insert(name, Rc::new(RefCell::new(value)));
+        }
+
+        /// Get a mutable handle scope to the desired [`Scope`]
+        pub fn get_scope(&mut self, path: &mut Iter<usize>) -> &mut Scope {
+            let scope = path.next();
+
+            if let Some(scope) = scope {
+                self.scopes.get_mut(scope).unwrap().get_scope(path)
+            } else {
+                self
+            }
+        }
+
+        /// Get the frame of the scope at the end of the path
+        fn get_frame(&self, path: &mut Iter<usize>) -> FrameRef {
+            match path.next() {
+                Some(scope) => self.scopes[scope].get_frame(path),
+                None => self.frame.clone(),
+            }
+        }
+
+        /// Get a mutable handle to the desired [`RuntimeFunction`]
+        pub fn get_function(
+            &mut self,
+            name: impl AsRef<str>,
+            path: &mut Iter<usize>,
+        ) -> Option<Rc<RefCell<dyn RuntimeFunction>>> {
+            let variable = self.get_binding(name.as_ref(), path)?;
+            let function = variable.borrow().as_func();
+            function
+        }
+
+        /// Call a function given a name, a scope ID and arguments
+        pub fn call_function(
+            &mut self,
+            name: impl AsRef<str>,
+            path: &mut Iter<usize>,
+            args: Vec<LenarValue>,
+            parser: &Parser,
+        ) -> LenarResult<LenarValue> {
+            let func_name = name.as_ref().to_string();
+            let func = self.get_function(name, path);
+
+            if let Some(func) = func {
+                call_runtime_function(&func, args, parser)
+            } else {
+                Err(LenarError::VariableNotFound(func_name))
+            }
+        }
+
+        /// Define a variable with a given name and a value in the specified scope ID
+        pub fn define_variable(
+            &mut self,
+            name: impl AsRef<str>,
+            scope_path: &[usize],
+            value: LenarValue,
+        ) {
+            let scope = self.get_scope(&mut scope_path.iter());
+            scope.insert_variable(name.as_ref().to_string(), value);
+        }
+
+        /// Find the binding of the closest variable with the given name
+        fn get_binding(&self, name: &str, path: &mut Iter<usize>) -> Option<Binding> {
+            Frame::find(&self.get_frame(path), name)
+        }
+
+        /// Update the value of the closest variable with the given name, values held
+        /// by a [`LenarValue::Ref`] are updated through it
+        pub fn assign_variable(
+            &mut self,
+            name: impl AsRef<str>,
+            path: &mut Iter<usize>,
+            value: LenarValue,
+        ) -> LenarResult<()> {
+            let binding = self
+                .get_binding(name.as_ref(), path)
+                .ok_or_else(|| LenarError::VariableNotFound(name.as_ref().to_owned()))?;
+
+            let reference = match &*binding.borrow() {
+                LenarValue::Ref(reference) => Some(reference.clone()),
+                _ => None,
+            };
+            match reference {
+                Some(reference) => *reference.borrow_mut() = value.dereferenced(),
+                None => *binding.borrow_mut() = value,
+            }
+
+            Ok(())
+        }
+
+        /// Resolve a variable value given it's name and the caller scope ID
+        pub fn get_variable(
+            &mut self,
+            name: impl AsRef<str>,
+            path: &mut Iter<usize>,
+        ) -> LenarResult<LenarValue> {
+            // Currently referencing a variable clones it's value,
+            // Once I add proper value-movements I will do this by calling
+            // `variables.remove(name.as_ref())` and without the `clone()`
+            // This way the variable's owned value will get removed from the scope folder
+            // and returned to the variable referencer
+            let var_name = name.as_ref().to_owned();
+            let var = self.get_binding(name.as_ref(), path);
+            if let Some(var) = var {
+                let value = var.borrow().clone();
+                Ok(value)
+            } else {
+                Err(LenarError::VariableNotFound(var_name))
+            }
+        }
+
+        pub fn get_variable_by_path(
+            &mut self,
+            var_path: &[String],
+            path: &mut Iter<usize>,
+        ) -> LenarResult<LenarValue> {
+            let mut var_path = var_path.iter();
+
+            let var_holder = var_path.next().unwrap();
+            let var_holder_value = self
+                .get_binding(var_holder, path)
+                .map(|var| var.borrow().clone().dereferenced());
+            if let Some(LenarValue::Instance(instance)) = var_holder_value {
+                let instance = instance.borrow_mut();
+                instance.get_props(&mut var_path)
+            } else {
+                Err(LenarError::VariableNotFound(var_holder.clone()))
+            }
+        }
+
+        /// Create a new scope given an ID in the specified scope by a path
+        pub fn create_scope(&mut self, scope_path: &[usize], scope_id: usize) {
+            let scope = self.get_scope(&mut scope_path.iter());
+            let new_scope = Scope::with_frame(Frame::child_of(&scope.frame));
+
+            scope.scopes.insert(scope_id, new_scope);
+        }
+
+        /// Drop a scope given an ID and a scope path
+        pub fn drop_scope(&mut self, scope_path: &[usize], scope_id: usize) {
+            let scope = self.get_scope(&mut scope_path.iter());
+            scope.scopes.remove(&scope_id);
+        }
+    }
+
+    /// Interrupts the evaluation of the code until something handles it
+    #[derive(Debug)]
+    enum Unwind {
+        Error(LenarError),
+        /// `break`, handled by the closest loop
+        Break(LenarValue),
+        /// `continue`, handled by the closest loop
+        Continue,
+        /// `return`, handled by the closest function call
+        Return(LenarValue),
+    }
+
+    impl From<LenarError> for Unwind {
+        fn from(err: LenarError) -> Self {
+            Self::Error(err)
+        }
+    }
+
+    impl Unwind {
+        /// Get the error once the unwinding reaches a function boundary
+        fn into_error(self) -> LenarError {
+            match self {
+                Self::Error(err) => err,
+                Self::Break(_) | Self::Continue | Self::Return(_) => unreachable!(
+                    "The parser only allows `break` and `continue` inside loops, and `return` inside functions"
+                ),
+            }
+        }
+    }
+
+    type EvaluationResult = Result<LenarValue, Unwind>;
+
+    /// Handle the result of a loop iteration, returning the loop result if it must stop
+    fn loop_exit(iteration: EvaluationResult) -> Option<EvaluationResult> {
+        match iteration {
+            Ok(_) | Err(Unwind::Continue) => None,
+            Err(Unwind::Break(value)) => Some(Ok(value)),
+            Err(unwind) => Some(Err(unwind)),
+        }
+    }
+
+    /// Evaluate a [`ParserObject`] in a new scope with the given variables, the scope is
+    /// identified by the object key and gets removed afterwards.
+    fn evaluate_in_scope(
+        object_key: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+        variables: impl IntoIterator<Item = (String, LenarValue)>,
+    ) -> EvaluationResult {
+        scope.create_scope(scope_path, object_key);
+
+        let inner_path = [scope_path, &[object_key]].concat();
+        for (name, value) in variables {
+            scope.define_variable(name, &inner_path, value);
+        }
+        let result = evaluate_object(object_key, parser, scope, &inner_path, None);
+
+        scope.drop_scope(scope_path, object_key);
+        result
+    }
+
+    /// Instance of a struct declared in lenar code
+    #[derive(Debug)]
+    struct Record {
+        struct_name: Rc<str>,
+        fields: Rc<[String]>,
+        /// Value of each field, in the same order as `fields`
+        values: Vec<LenarValue>,
+    }
+
+    impl RuntimeInstance for Record {
+        fn get_prop(&self, prop: &str) -> LenarValue {
+            self.fields
+                .iter()
+                .position(|field| field == prop)
+                .map(|i| self.values[i].clone())
+                .unwrap_or(LenarValue::Void)
+        }
+
+        fn get_fields(&self) -> Option<Vec<(String, LenarValue)>> {
+            Some(
+                self.fields
+                    .iter()
+                    .cloned()
+                    .zip(self.values.clone())
+                    .collect(),
+            )
+        }
+
+        fn set_prop(&mut self, prop: &str, value: LenarValue) -> LenarResult<()> {
+            match self.fields.iter().position(|field| field == prop) {
+                Some(i) => {
+                    self.values[i] = value.dereferenced();
+                    Ok(())
+                }
+                None => Err(LenarError::PropertyNotAssignable {
+                    holder: self.struct_name.to_string(),
+                    property: prop.to_owned(),
+                }),
+            }
+        }
+
+        fn get_name(&self) -> &str {
+            &self.struct_name
+        }
+    }
+
+    /// The value `catch` blocks get for an error, with its `kind`, its `message`
+    /// and the `value` given to `throw()`
+    fn caught_error(err: &LenarError) -> LenarValue {
+        let (message, value) = match err.root() {
+            LenarError::UserThrown(value) => (value.to_string(), LenarValue::from(value.clone())),
+            err => (err.to_string(), LenarValue::Void),
+        };
+        LenarValue::Instance(Rc::new(RefCell::new(Record {
+            struct_name: "Error".into(),
+            fields: ["kind", "message", "value"].map(String::from).into(),
+            values: vec![
+                LenarValue::Str(err.kind().to_owned()),
+                LenarValue::Str(message),
+                value,
+            ],
+        })))
+    }
+
+    /// Creates [`Record`]s out of the value of each field, such as `Point(1 2)`
+    #[derive(Debug)]
+    struct StructConstructor {
+        struct_name: Rc<str>,
+        fields: Rc<[String]>,
+    }
+
+    impl RuntimeFunction for StructConstructor {
+        fn call(&mut self, args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+            if args.len() != self.fields.len() {
+                return Err(LenarError::ArityMismatch {
+                    fn_name: self.struct_name.to_string(),
+                    expected: self.fields.len(),
+                    actual: args.len(),
+                });
+            }
+            Ok(LenarValue::Instance(Rc::new(RefCell::new(Record {
+                struct_name: self.struct_name.clone(),
+                fields: self.fields.clone(),
+                values: args.into_iter().map(LenarValue::dereferenced).collect(),
+            }))))
+        }
+
+        fn get_name(&self) -> &str {
+            &self.struct_name
+        }
+
+        fn kind(&self) -> FunctionKind {
+            FunctionKind::Constructor
+        }
+
+        fn arity(&self) -> Option<usize> {
+            Some(self.fields.len())
+        }
+    }
+
+    /// Define the constructor of a [`ParserObject::StructDef`]
+    fn declare_struct(
+        struct_name: &str,
+        fields: &[String],
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) {
+        let constructor = StructConstructor {
+            struct_name: struct_name.into(),
+            fields: fields.into(),
+        };
+        scope.define_variable(
+            struct_name,
+            scope_path,
+            LenarValue::Function(Rc::new(RefCell::new(constructor))),
+        );
+    }
+
+    /// Creates the [`LenarEnum`] of a variant out of its payload, such as `Circle(2)`
+    #[derive(Debug)]
+    struct VariantConstructor {
+        variant_name: String,
+        fields: usize,
+    }
+
+    impl RuntimeFunction for VariantConstructor {
+        fn call(&mut self, args: Vec<LenarValue>, _parser: &Parser) -> LenarResult<LenarValue> {
+            if args.len() != self.fields {
+                return Err(LenarError::ArityMismatch {
+                    fn_name: self.variant_name.clone(),
+                    expected: self.fields,
+                    actual: args.len(),
+                });
+            }
+            let mut args = args.into_iter().map(LenarValue::dereferenced);
+
+            // Payloads with several fields are kept as a list
+            let payload = match self.fields {
+                1 => args.next().unwrap(),
+                _ => LenarValue::List(args.collect()),
+            };
+            Ok(LenarValue::Enum(LenarEnum::new_with_variant(
+                self.variant_name.clone(),
+                payload,
+            )))
+        }
+
+        fn get_name(&self) -> &str {
+            &self.variant_name
+        }
+
+        fn kind(&self) -> FunctionKind {
+            FunctionKind::Constructor
+        }
+
+        fn arity(&self) -> Option<usize> {
+            Some(self.fields)
+        }
+    }
+
+    /// Define the variants of a [`ParserObject::EnumDef`], as constructors or as values
+    /// when they have no payload
+    fn declare_enum(variants: &[(String, Vec<String>)], scope: &mut Scope, scope_path: &[usize]) {
+        for (variant_name, fields) in variants {
+            let value = if fields.is_empty() {
+                LenarValue::Enum(LenarEnum::new_with_variant(
+                    variant_name.clone(),
+                    LenarValue::Void,
+                ))
+            } else {
+                LenarValue::Function(Rc::new(RefCell::new(VariantConstructor {
+                    variant_name: variant_name.clone(),
+                    fields: fields.len(),
+                })))
+            };
+            scope.define_variable(variant_name, scope_path, value);
+        }
+    }
+
+    /// Check a value against a [`MatchPattern`], getting the variables it binds if it matches
+    fn match_pattern(
+        pattern: &MatchPattern,
+        value: &LenarValue,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> Result<Option<Vec<(String, LenarValue)>>, Unwind> {
+        match pattern {
+            MatchPattern::Wildcard => Ok(Some(Vec::new())),
+            MatchPattern::Literal(literal) => {
+                let literal = evaluate_object(*literal, parser, scope, scope_path, None)?;
+                Ok((literal == *value).then(Vec::new))
+            }
+            MatchPattern::Variant {
+                variant_name,
+                bindings,
+            } => {
+                let LenarValue::Enum(en) = value else {
+                    return Ok(None);
+                };
+                let Some(payload) = en.peek_variant(variant_name) else {
+                    return Ok(None);
+                };
+
+                let values = match (bindings.len(), payload) {
+                    (0, _) => Vec::new(),
+                    (1, payload) => vec![payload.clone()],
+                    (len, LenarValue::List(items)) if items.len() == len => items.clone(),
+                    _ => return Ok(None),
+                };
+                Ok(Some(
+                    bindings
+                        .iter()
+                        .cloned()
+                        .zip(values)
+                        .filter(|(name, _)| name != "_")
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    /// Function created at runtime
+    #[derive(Debug, Clone)]
+    struct Function {
+        definition: Rc<FunctionDefinition>,
+    }
+
+    /// Code of a [`Function`] and the values it sees
+    #[derive(Debug)]
+    struct FunctionDefinition {
+        fn_name: Option<String>,
+        capture_area: HashMap<String, LenarValue>,
+        /// Frame of the scope where the function was created
+        frame: FrameRef,
+        /// Names of `frame` the function sees, all of them when unset
+        visible: Option<Rc<HashSet<String>>>,
+        arguments_block: ParserObjectKey,
+        /// Number of arguments it takes
+        arity: usize,
+        block_value: ParserObjectKey,
+    }
+
+    impl RuntimeFunction for Function {
+        fn call(&mut self, args: Vec<LenarValue>, parser: &Parser) -> LenarResult<LenarValue> {
+            let definition = &self.definition;
+
+            // Functions see the variables of the scope they were created in as they are now
+            let mut scope = Scope::with_frame(Frame {
+                visible: definition.visible.clone(),
+                ..Frame::child_of(&definition.frame)
+            });
+
+            // Define each argument as a variable in the function scope
+            if args.len() != definition.arity {
+                return Err(LenarError::ArityMismatch {
+                    fn_name: self.get_name().to_owned(),
+                    expected: definition.arity,
+                    actual: args.len(),
+                });
+            }
+            let arguments_block = parser.get_object(definition.arguments_block).unwrap();
+            if let ParserObject::Block { objects } = arguments_block {
+                for (object, arg_value) in objects.iter().zip(args) {
+                    let arg_object = parser.get_object(*object).unwrap();
+                    if let ParserObject::VarRef { var_name } = arg_object {
+                        scope.insert_variable(var_name.to_owned(), arg_value);
                     }
                 }
             }
 
-            impl RuntimeFunction for PrintFunc {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    for val in args {
-                        Self::write(&val);
+            // Inject every captured value in the function scope
+            for (captured_var, value) in definition.capture_area.iter() {
+                scope.insert_variable(captured_var.to_owned(), value.clone());
+            }
+
+            match evaluate_object(definition.block_value, parser, &mut scope, &[], None) {
+                Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+                Err(unwind) => Err(unwind.into_error()),
+            }
+        }
+
+        fn get_name(&self) -> &str {
+            self.definition.fn_name.as_deref().unwrap_or("Anonymous")
+        }
+
+        fn kind(&self) -> FunctionKind {
+            FunctionKind::Closure
+        }
+
+        fn arity(&self) -> Option<usize> {
+            Some(self.definition.arity)
+        }
+
+        fn reentrant_handle(&self) -> Option<Box<dyn RuntimeFunction>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    /// Create the [`Function`] of a [`ParserObject::FnDef`], `declared` are the names
+    /// of the functions declared along it
+    fn create_function(
+        object_key: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+        declared: &Rc<HashSet<String>>,
+    ) -> LenarResult<LenarValue> {
+        let Some(ParserObject::FnDef {
+            fn_name,
+            arguments_block,
+            block_value,
+            capture_value,
+        }) = parser.get_object(object_key)
+        else {
+            unreachable!("Functions are only created out of function definitions")
+        };
+
+        let frame = scope.get_frame(&mut scope_path.iter());
+        let arity = parser
+            .get_object(*arguments_block)
+            .and_then(ParserObject::objects)
+            .map_or(0, <[_]>::len);
+
+        // Without a capture area the function sees every variable of the scope
+        let Some(capture_value) = capture_value else {
+            let function = Rc::new(RefCell::new(Function {
+                definition: Rc::new(FunctionDefinition {
+                    fn_name: fn_name.clone(),
+                    capture_area: HashMap::default(),
+                    frame: frame.clone(),
+                    visible: None,
+                    arguments_block: *arguments_block,
+                    arity,
+                    block_value: *block_value,
+                }),
+            }));
+            frame.borrow_mut().add_function(&function);
+            return Ok(LenarValue::Function(function));
+        };
+
+        let capture_area_value = parser.get_object(*capture_value);
+
+        let capture_area = {
+            let mut capture_area = HashMap::default();
+            if let Some(ParserObject::Block { objects }) = capture_area_value {
+                for object_key in objects {
+                    let object_value = parser.get_object(*object_key);
+                    if let Some(ParserObject::VarRef { var_name }) = object_value {
+                        // Declared functions are always available
+                        if declared.contains(var_name) {
+                            continue;
+                        }
+                        let var_value = scope.get_variable(var_name, &mut scope_path.iter())?;
+                        capture_area.insert(
+                            var_name.clone(),
+                            LenarValue::Ref(Rc::new(RefCell::new(var_value))),
+                        );
                     }
-                    stdout().flush().ok();
-                    Ok(LenarValue::Void)
                 }
+            }
+            capture_area
+        };
+
+        // Out of the scope, only the declared functions are seen
+        let mut definition = FunctionDefinition {
+            fn_name: fn_name.clone(),
+            capture_area,
+            frame: Frame::globals(&frame),
+            visible: None,
+            arguments_block: *arguments_block,
+            arity,
+            block_value: *block_value,
+        };
+        if declared.is_empty() {
+            return Ok(LenarValue::Function(Rc::new(RefCell::new(Function {
+                definition: Rc::new(definition),
+            }))));
+        }
 
-                fn get_name(&self) -> &str {
-                    "print"
+        definition.frame = frame.clone();
+        definition.visible = Some(declared.clone());
+        let function = Rc::new(RefCell::new(Function {
+            definition: Rc::new(definition),
+        }));
+        frame.borrow_mut().add_function(&function);
+        Ok(LenarValue::Function(function))
+    }
+
+    /// Define the functions declared in a block before running it, so they can
+    /// call themselves and each other no matter the order.
+    fn declare_functions(
+        objects: &[ParserObjectKey],
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> LenarResult<()> {
+        // Structs and enums come first so functions can construct them
+        for object_key in objects {
+            match parser.get_object(*object_key) {
+                Some(ParserObject::StructDef {
+                    struct_name,
+                    fields,
+                }) => declare_struct(struct_name, fields, scope, scope_path),
+                Some(ParserObject::EnumDef { variants, .. }) => {
+                    declare_enum(variants, scope, scope_path)
                 }
+                _ => {}
             }
+        }
 
-            // println()
-            #[derive(Debug)]
-            struct PrintLnFunc;
+        let declarations = objects
+            .iter()
+            .filter_map(|object_key| match parser.get_object(*object_key) {
+                Some(ParserObject::FnDef {
+                    fn_name: Some(fn_name),
+                    ..
+                }) => Some((*object_key, fn_name)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let declared = Rc::new(
+            declarations
+                .iter()
+                .map(|(_, fn_name)| fn_name.to_string())
+                .collect::<HashSet<_>>(),
+        );
+
+        for (object_key, fn_name) in declarations {
+            let function = create_function(object_key, parser, scope, scope_path, &declared)?;
+            scope.define_variable(fn_name, scope_path, function);
+        }
 
-            impl RuntimeFunction for PrintLnFunc {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    for val in args {
-                        PrintFunc::write(&val);
-                    }
-                    stdout().write("\n".as_bytes()).ok();
-                    stdout().flush().ok();
-                    Ok(LenarValue::Void)
+        Ok(())
+    }
+
+    fn as_bool(value: &LenarValue) -> LenarResult<bool> {
+        value
+            .as_bool()
+            .ok_or_else(|| LenarError::type_mismatch("bool", value))
+    }
+
+    /// Apply a [`BinaryOperator`] to already evaluated operands
+    fn apply_binary_operator(
+        operator: BinaryOperator,
+        left: &LenarValue,
+        right: &LenarValue,
+    ) -> LenarResult<LenarValue> {
+        match operator {
+            BinaryOperator::Add => left.checked_add(right),
+            BinaryOperator::Sub => left.checked_sub(right),
+            BinaryOperator::Mul => left.checked_mul(right),
+            BinaryOperator::Div => left.checked_div(right),
+            BinaryOperator::Rem => left.checked_rem(right),
+            BinaryOperator::Equal => Ok(LenarValue::Bool(left == right)),
+            BinaryOperator::NotEqual => Ok(LenarValue::Bool(left != right)),
+            BinaryOperator::Less => Ok(LenarValue::Bool(left.compare(right)?.is_lt())),
+            BinaryOperator::LessEqual => Ok(LenarValue::Bool(left.compare(right)?.is_le())),
+            BinaryOperator::Greater => Ok(LenarValue::Bool(left.compare(right)?.is_gt())),
+            BinaryOperator::GreaterEqual => Ok(LenarValue::Bool(left.compare(right)?.is_ge())),
+            BinaryOperator::And => Ok(LenarValue::Bool(as_bool(left)? && as_bool(right)?)),
+            BinaryOperator::Or => Ok(LenarValue::Bool(as_bool(left)? || as_bool(right)?)),
+        }
+    }
+
+    /// Where an assignment stores its value, with the indexes already evaluated
+    enum Place<'a> {
+        Variable(&'a str),
+        /// Position of a list or key of a map, held in another place
+        Index {
+            holder: Box<Place<'a>>,
+            index: LenarValue,
+        },
+        Property {
+            instance: Rc<RefCell<dyn RuntimeInstance>>,
+            property: &'a str,
+        },
+    }
+
+    /// Find the [`Place`] referred by `target`, evaluating its indexes and holders once
+    fn resolve_place<'a>(
+        target: ParserObjectKey,
+        parser: &'a Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> Result<Place<'a>, Unwind> {
+        match parser.get_object(target).unwrap() {
+            ParserObject::VarRef { var_name } => Ok(Place::Variable(var_name)),
+            ParserObject::Index {
+                value: holder,
+                index,
+            } => {
+                let holder = resolve_place(*holder, parser, scope, scope_path)?;
+                let index = evaluate_object(*index, parser, scope, scope_path, None)?;
+                Ok(Place::Index {
+                    holder: Box::new(holder),
+                    index,
+                })
+            }
+            ParserObject::PropertyRef { path } => {
+                let (property, holder_path) = path.split_last().unwrap();
+                let holder = match holder_path {
+                    [holder] => scope.get_variable(holder, &mut scope_path.iter())?,
+                    _ => scope.get_variable_by_path(holder_path, &mut scope_path.iter())?,
+                };
+                match holder.dereferenced() {
+                    LenarValue::Instance(instance) => Ok(Place::Property { instance, property }),
+                    holder => Err(LenarError::type_mismatch("instance", &holder).into()),
+                }
+            }
+            _ => unreachable!("The parser only allows assigning variables, properties and indexes"),
+        }
+    }
+
+    /// Get the value stored in a [`Place`]
+    fn read_place(
+        place: &Place,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> LenarResult<LenarValue> {
+        match place {
+            Place::Variable(var_name) => scope.get_variable(var_name, &mut scope_path.iter()),
+            Place::Index { holder, index } => {
+                read_place(holder, scope, scope_path)?.get_index(index)
+            }
+            Place::Property { instance, property } => Ok(instance.borrow().get_prop(property)),
+        }
+    }
+
+    /// Store a value in a [`Place`]
+    fn write_place(
+        place: &Place,
+        value: LenarValue,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> LenarResult<()> {
+        match place {
+            Place::Variable(var_name) => {
+                scope.assign_variable(var_name, &mut scope_path.iter(), value)
+            }
+            // The updated holder is stored back where it came from
+            Place::Index { holder, index } => {
+                let mut holder_value = read_place(holder, scope, scope_path)?;
+                holder_value.set_index(index, value)?;
+                write_place(holder, holder_value, scope, scope_path)
+            }
+            Place::Property { instance, property } => {
+                instance.borrow_mut().set_prop(property, value)
+            }
+        }
+    }
+
+    /// Evaluate a [`ParserObject`] to a [`LenarValue`]
+    ///
+    /// Objects that evaluate other objects are handled by their own function, that way
+    /// each level of nesting only takes the stack of what it really runs.
+    fn evaluate_object(
+        object_key: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+        scope_position: Option<usize>,
+    ) -> EvaluationResult {
+        let depth = EVALUATION_DEPTH.get();
+        let max_evaluation_depth = MAX_EVALUATION_DEPTH.get();
+        if depth >= max_evaluation_depth {
+            return Err(Unwind::Error(LenarError::ResourceLimitExceeded {
+                resource: "nested evaluations".to_owned(),
+                limit: max_evaluation_depth,
+            }));
+        }
+
+        EVALUATION_DEPTH.set(depth + 1);
+        let object = parser.get_object(object_key).unwrap();
+        let result = match object {
+            ParserObject::Block { objects } => {
+                let objects = &objects[scope_position.unwrap_or(0)..];
+                evaluate_block(objects, parser, scope, scope_path)
+            }
+            ParserObject::VarDef {
+                var_name,
+                block_value,
+            } => evaluate_var_def(var_name, *block_value, parser, scope, scope_path),
+            ParserObject::StructDef {
+                struct_name,
+                fields,
+            } => {
+                declare_struct(struct_name, fields, scope, scope_path);
+                Ok(LenarValue::Void)
+            }
+            ParserObject::EnumDef { variants, .. } => {
+                declare_enum(variants, scope, scope_path);
+                Ok(LenarValue::Void)
+            }
+            ParserObject::Propagate { value } => {
+                evaluate_propagate(*value, parser, scope, scope_path)
+            }
+            ParserObject::TryDef {
+                block_value,
+                var_name,
+                catch_block,
+            } => evaluate_try(
+                *block_value,
+                var_name,
+                *catch_block,
+                parser,
+                scope,
+                scope_path,
+            ),
+            ParserObject::MatchDef { value, arms } => {
+                evaluate_match(*value, arms, parser, scope, scope_path)
+            }
+            ParserObject::FunctionCall { arguments, fn_name } => {
+                evaluate_call(object_key, *arguments, fn_name, parser, scope, scope_path)
+            }
+            ParserObject::StringVal { value } => Ok(LenarValue::Str(value.to_string())), // TODO: Optimize this
+            ParserObject::BytesVal { value } => Ok(LenarValue::Bytes(value.to_owned())), // TODO: Optimize this
+            ParserObject::TemplateVal { segments } => {
+                evaluate_template(segments, parser, scope, scope_path)
+            }
+            ParserObject::VarRef { var_name } => scope
+                .get_variable(var_name, &mut scope_path.iter())
+                .map_err(Unwind::Error),
+            ParserObject::PropertyRef { path } => scope
+                .get_variable_by_path(path, &mut scope_path.iter())
+                .map_err(Unwind::Error),
+            ParserObject::FnDef { fn_name: None, .. } => {
+                create_function(object_key, parser, scope, scope_path, &Rc::default())
+                    .map_err(Unwind::Error)
+            }
+            // Declarations are defined by the block containing them
+            ParserObject::FnDef {
+                fn_name: Some(_), ..
+            } => Ok(LenarValue::Void),
+            ParserObject::IfDef {
+                condition_block,
+                block_value,
+                else_block,
+            } => evaluate_if(
+                *condition_block,
+                *block_value,
+                *else_block,
+                parser,
+                scope,
+                scope_path,
+            ),
+            ParserObject::NumberVal { value } => Ok(LenarValue::Usize(*value)),
+            ParserObject::IntegerVal { value } => Ok(LenarValue::Integer(*value)),
+            ParserObject::FloatVal { value } => Ok(LenarValue::Float(*value)),
+            ParserObject::BoolVal { value } => Ok(LenarValue::Bool(*value)),
+            ParserObject::ListVal { items } => evaluate_list(*items, parser, scope, scope_path),
+            ParserObject::Index { value, index } => {
+                evaluate_index(*value, *index, parser, scope, scope_path)
+            }
+            ParserObject::Slice { value, start, end } => {
+                evaluate_slice(*value, *start, *end, parser, scope, scope_path)
+            }
+            ParserObject::MapVal { entries } => evaluate_map(entries, parser, scope, scope_path),
+            ParserObject::BinaryOp {
+                operator,
+                left,
+                right,
+            } => evaluate_binary_op(*operator, *left, *right, parser, scope, scope_path),
+            ParserObject::Assignment {
+                target,
+                operator,
+                value,
+            } => evaluate_assignment(*target, *operator, *value, parser, scope, scope_path),
+            ParserObject::UnaryOp { operator, value } => {
+                evaluate_unary_op(*operator, *value, parser, scope, scope_path)
+            }
+            ParserObject::WhileDef {
+                condition_block,
+                block_value,
+            } => evaluate_while(*condition_block, *block_value, parser, scope, scope_path),
+            ParserObject::ForDef {
+                var_name,
+                iterable,
+                block_value,
+            } => evaluate_for(var_name, *iterable, *block_value, parser, scope, scope_path),
+            ParserObject::Break { value } => evaluate_optional(*value, parser, scope, scope_path)
+                .and_then(|value| Err(Unwind::Break(value))),
+            ParserObject::Continue => Err(Unwind::Continue),
+            ParserObject::Return { value } => evaluate_optional(*value, parser, scope, scope_path)
+                .and_then(|value| Err(Unwind::Return(value))),
+        };
+        EVALUATION_DEPTH.set(depth);
+        result
+    }
+
+    /// Evaluate a [`ParserObject::VarDef`]
+    fn evaluate_var_def(
+        var_name: &str,
+        block_value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let res = evaluate_object(block_value, parser, scope, scope_path, None)?;
+        scope.define_variable(var_name, scope_path, res);
+
+        Ok(LenarValue::Void)
+    }
+
+    /// Evaluate a [`ParserObject::TryDef`], only errors are caught, `break`, `continue`
+    /// and `return` go through
+    fn evaluate_try(
+        block_value: ParserObjectKey,
+        var_name: &str,
+        catch_block: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        match evaluate_object(block_value, parser, scope, scope_path, None) {
+            Err(Unwind::Error(err)) => evaluate_in_scope(
+                catch_block,
+                parser,
+                scope,
+                scope_path,
+                [(var_name.to_owned(), caught_error(&err))],
+            ),
+            result => result,
+        }
+    }
+
+    /// Evaluate a [`ParserObject::IfDef`]
+    fn evaluate_if(
+        condition_block: ParserObjectKey,
+        block_value: ParserObjectKey,
+        else_block: Option<ParserObjectKey>,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let expr_res = evaluate_object(condition_block, parser, scope, scope_path, None)?;
+
+        // If the condition expression returns a `true` it
+        // will evaluate the actual block, otherwise the `else` branch
+        if LenarValue::Bool(true) == expr_res {
+            evaluate_object(block_value, parser, scope, scope_path, None)
+        } else if let Some(else_block) = else_block {
+            evaluate_object(else_block, parser, scope, scope_path, None)
+        } else {
+            Ok(LenarValue::Void)
+        }
+    }
+
+    /// Evaluate a [`ParserObject::Index`]
+    fn evaluate_index(
+        value: ParserObjectKey,
+        index: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = evaluate_object(value, parser, scope, scope_path, None)?;
+        let index = evaluate_object(index, parser, scope, scope_path, None)?;
+        Ok(value.get_index(&index)?)
+    }
+
+    /// Evaluate a [`ParserObject::UnaryOp`]
+    fn evaluate_unary_op(
+        operator: UnaryOperator,
+        value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = evaluate_object(value, parser, scope, scope_path, None)?;
+        let value = match operator {
+            UnaryOperator::Not => value
+                .as_bool()
+                .map(|value| LenarValue::Bool(!value))
+                .ok_or_else(|| LenarError::type_mismatch("bool", &value)),
+            UnaryOperator::Neg => value.checked_neg(),
+        }?;
+        Ok(value)
+    }
+
+    /// Evaluate the value of a `break` or a `return`, which is `Void` when missing
+    fn evaluate_optional(
+        value: Option<ParserObjectKey>,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        match value {
+            Some(value) => evaluate_object(value, parser, scope, scope_path, None),
+            None => Ok(LenarValue::Void),
+        }
+    }
+
+    /// Evaluate the objects of a [`ParserObject::Block`], which gives the value of the last one
+    fn evaluate_block(
+        objects: &[ParserObjectKey],
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        declare_functions(objects, parser, scope, scope_path)?;
+
+        let mut value = LenarValue::Void;
+        for object_key in objects {
+            value = match parser.get_object(*object_key).unwrap() {
+                // Blocks run in their own scope
+                ParserObject::Block { .. } => {
+                    evaluate_in_scope(*object_key, parser, scope, scope_path, [])?
+                }
+                // Already declared
+                ParserObject::FnDef {
+                    fn_name: Some(_), ..
+                }
+                | ParserObject::StructDef { .. }
+                | ParserObject::EnumDef { .. } => LenarValue::Void,
+                // Run the expression in the inherited scope
+                _ => evaluate_object(*object_key, parser, scope, scope_path, None)?,
+            };
+        }
+
+        Ok(value)
+    }
+
+    /// Evaluate a [`ParserObject::Propagate`], which gives the payload of an `Ok` or a `Some`
+    fn evaluate_propagate(
+        value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = evaluate_object(value, parser, scope, scope_path, None)?.dereferenced();
+        match &value {
+            LenarValue::Enum(variants) => {
+                if let Some(payload) = variants.peek_success() {
+                    Ok(payload.clone())
+                } else if variants.is_failure() {
+                    // The function returns the `Err` or `None` as it is
+                    Err(Unwind::Return(value))
+                } else {
+                    Err(LenarError::type_mismatch("Ok or Some", &value).into())
+                }
+            }
+            _ => Err(LenarError::type_mismatch("Ok or Some", &value).into()),
+        }
+    }
+
+    /// Evaluate a [`ParserObject::MatchDef`], the first matching arm gives the value,
+    /// without one it's `Void`
+    fn evaluate_match(
+        value: ParserObjectKey,
+        arms: &[MatchArm],
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = evaluate_object(value, parser, scope, scope_path, None)?.dereferenced();
+
+        for arm in arms {
+            if let Some(bindings) = match_pattern(&arm.pattern, &value, parser, scope, scope_path)?
+            {
+                return evaluate_in_scope(arm.block_value, parser, scope, scope_path, bindings);
+            }
+        }
+        Ok(LenarValue::Void)
+    }
+
+    /// Evaluate a [`ParserObject::FunctionCall`]
+    fn evaluate_call(
+        object_key: ParserObjectKey,
+        arguments: ParserObjectKey,
+        fn_name: &str,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = parser.get_object(arguments).unwrap();
+        let mut args = Vec::new();
+        if let ParserObject::Block { objects } = value {
+            for tok in objects {
+                let res = evaluate_object(*tok, parser, scope, scope_path, None)?;
+
+                args.push(res);
+            }
+        }
+
+        let Some(func) = scope.get_function(fn_name, &mut scope_path.iter()) else {
+            // Tell apart missing variables from those that hold something else
+            return Err(match scope.get_variable(fn_name, &mut scope_path.iter()) {
+                Ok(_) => LenarError::NotCallable(fn_name.to_owned()),
+                Err(err) => err,
+            }
+            .into());
+        };
+
+        // Only native functions can be borrowed while they run
+        let kind = func
+            .try_borrow()
+            .map_or(FunctionKind::Native, |func| func.kind());
+
+        // Errors coming from the called function get the call site added to their stack
+        let value = call_runtime_function(&func, args, parser).map_err(|err| {
+            let span = parser.get_span(object_key).unwrap_or_default();
+            err.with_frame(StackFrame {
+                fn_name: fn_name.to_owned(),
+                span,
+                location: parser.get_location(span),
+                kind,
+            })
+        })?;
+        Ok(value)
+    }
+
+    /// Evaluate a [`ParserObject::TemplateVal`] to the text of its segments
+    fn evaluate_template(
+        segments: &[TemplateSegment],
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let mut text = String::new();
+        for segment in segments {
+            match segment {
+                TemplateSegment::Text(segment) => text.push_str(segment),
+                TemplateSegment::Expression(expression) => {
+                    let value = evaluate_object(*expression, parser, scope, scope_path, None)?;
+                    text.push_str(&value.to_string());
+                }
+            }
+        }
+        Ok(LenarValue::Str(text))
+    }
+
+    /// Evaluate the items of a [`ParserObject::ListVal`]
+    fn evaluate_list(
+        items: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let mut list = Vec::new();
+        if let Some(ParserObject::Block { objects }) = parser.get_object(items) {
+            for object in objects {
+                list.push(evaluate_object(*object, parser, scope, scope_path, None)?);
+            }
+        }
+        Ok(LenarValue::List(list))
+    }
+
+    /// Evaluate a [`ParserObject::Slice`], missing bounds go to the start or the end
+    fn evaluate_slice(
+        value: ParserObjectKey,
+        start: Option<ParserObjectKey>,
+        end: Option<ParserObjectKey>,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let value = evaluate_object(value, parser, scope, scope_path, None)?;
+        let mut bound = |bound: Option<ParserObjectKey>| -> Result<_, Unwind> {
+            match bound {
+                Some(bound) => {
+                    let bound = evaluate_object(bound, parser, scope, scope_path, None)?;
+                    bound
+                        .as_integer()
+                        .map(Some)
+                        .ok_or_else(|| LenarError::type_mismatch("index", &bound).into())
                 }
+                None => Ok(None),
+            }
+        };
+        let (start, end) = (bound(start)?, bound(end)?);
+        Ok(value.slice(start, end)?)
+    }
+
+    /// Evaluate the entries of a [`ParserObject::MapVal`]
+    fn evaluate_map(
+        entries: &[(String, ParserObjectKey)],
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let mut map = BTreeMap::new();
+        for (key, value) in entries {
+            let value = evaluate_object(*value, parser, scope, scope_path, None)?;
+            map.insert(key.clone(), value);
+        }
+        Ok(LenarValue::Map(map))
+    }
+
+    /// Evaluate a [`ParserObject::BinaryOp`]
+    fn evaluate_binary_op(
+        operator: BinaryOperator,
+        left: ParserObjectKey,
+        right: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let left = evaluate_object(left, parser, scope, scope_path, None)?;
+
+        // Logical operators only evaluate the right side when needed
+        match operator {
+            BinaryOperator::And if !as_bool(&left)? => return Ok(LenarValue::Bool(false)),
+            BinaryOperator::Or if as_bool(&left)? => return Ok(LenarValue::Bool(true)),
+            _ => {}
+        }
+
+        let right = evaluate_object(right, parser, scope, scope_path, None)?;
+        Ok(apply_binary_operator(operator, &left, &right)?)
+    }
+
+    /// Evaluate a [`ParserObject::Assignment`], compound ones apply their operator first
+    fn evaluate_assignment(
+        target: ParserObjectKey,
+        operator: Option<BinaryOperator>,
+        value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let mut value = evaluate_object(value, parser, scope, scope_path, None)?;
+        // The target is only evaluated once, compound ones read and write the same place
+        let place = resolve_place(target, parser, scope, scope_path)?;
+        if let Some(operator) = operator {
+            let current = read_place(&place, scope, scope_path)?;
+            value = apply_binary_operator(operator, &current, &value)?;
+        }
+
+        write_place(&place, value, scope, scope_path)?;
+        Ok(LenarValue::Void)
+    }
+
+    /// Evaluate a [`ParserObject::WhileDef`]
+    fn evaluate_while(
+        condition_block: ParserObjectKey,
+        block_value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        while LenarValue::Bool(true)
+            == evaluate_object(condition_block, parser, scope, scope_path, None)?
+        {
+            let iteration = evaluate_object(block_value, parser, scope, scope_path, None);
+            if let Some(result) = loop_exit(iteration) {
+                return result;
+            }
+        }
 
-                fn get_name(&self) -> &str {
-                    "println"
-                }
+        Ok(LenarValue::Void)
+    }
+
+    /// Evaluate a [`ParserObject::ForDef`]
+    fn evaluate_for(
+        var_name: &str,
+        iterable: ParserObjectKey,
+        block_value: ParserObjectKey,
+        parser: &Parser,
+        scope: &mut Scope,
+        scope_path: &[usize],
+    ) -> EvaluationResult {
+        let items = match evaluate_object(iterable, parser, scope, scope_path, None)?.dereferenced()
+        {
+            LenarValue::List(items) => items,
+            // Maps are iterated by their keys
+            LenarValue::Map(entries) => entries.into_keys().map(LenarValue::Str).collect(),
+            // Strings are iterated by their characters, like `chars()` splits them
+            value => match value.as_string() {
+                Some(string) => string
+                    .chars()
+                    .map(|c| LenarValue::Str(c.to_string()))
+                    .collect(),
+                None => return Err(LenarError::type_mismatch("iterable", &value).into()),
+            },
+        };
+
+        for item in items {
+            // Each iteration gets a new scope with the item in it
+            let iteration = evaluate_in_scope(
+                block_value,
+                parser,
+                scope,
+                scope_path,
+                [(var_name.to_owned(), item)],
+            );
+            if let Some(result) = loop_exit(iteration) {
+                return result;
             }
+        }
 
-            // isEqual()
-            #[derive(Debug)]
-            struct IsEqual;
+        Ok(LenarValue::Void)
+    }
 
-            impl RuntimeFunction for IsEqual {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let args = args.first().zip(args.get(1));
-                    let res = if let Some((a, b)) = args {
-                        a.eq(b)
-                    } else {
-                        false
-                    };
-                    Ok(LenarValue::Bool(res))
-                }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-                fn get_name(&self) -> &str {
-                    "isEqual"
-                }
+        fn evaluate(code: &str) -> LenarResult<LenarValue> {
+            let parser = Parser::new(code).unwrap();
+            Runtime::evaluate(&parser)
+        }
+
+        #[test]
+        fn errors_keep_the_calls_they_went_through() {
+            let code = "let inner = fn() [] { missing; };\nlet outer = fn() [inner] { inner(); };\nouter();";
+            let err = evaluate(code).unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "missing"));
+
+            let frames = err
+                .stack()
+                .iter()
+                .map(|frame| (frame.fn_name.as_str(), frame.kind, frame.location))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                frames,
+                [
+                    ("inner", FunctionKind::Closure, Some((2, 28))),
+                    ("outer", FunctionKind::Closure, Some((3, 1))),
+                ]
+            );
+        }
+
+        #[test]
+        fn strings_keep_their_characters() {
+            let value = evaluate(r#""caf\u{e9} \"ok\"""#).unwrap();
+            assert_eq!(value.to_string(), "café \"ok\"");
+        }
+
+        #[test]
+        fn numbers_of_every_kind() {
+            assert_eq!(evaluate("-7").unwrap(), LenarValue::Integer(-7));
+            assert_eq!(evaluate("0.5").unwrap(), LenarValue::Float(0.5));
+            assert_eq!(evaluate("-7").unwrap().to_string(), "-7");
+
+            // Numbers are equal across their kinds
+            assert_eq!(LenarValue::Usize(2), LenarValue::Float(2.0));
+            assert_eq!(LenarValue::Integer(2), LenarValue::Usize(2));
+            assert_ne!(LenarValue::Integer(-2), LenarValue::Usize(2));
+        }
+
+        #[test]
+        fn arithmetic_and_comparison() {
+            let cases = [
+                ("add(2 3)", LenarValue::Usize(5)),
+                ("sub(2 3)", LenarValue::Integer(-1)),
+                ("mul(-2 3)", LenarValue::Integer(-6)),
+                ("div(7 2)", LenarValue::Usize(3)),
+                ("div(7.0 2)", LenarValue::Float(3.5)),
+                ("mod(-7 3)", LenarValue::Integer(-1)),
+                ("pow(2 10)", LenarValue::Usize(1024)),
+                ("pow(2 -1)", LenarValue::Float(0.5)),
+                ("lt(1 1.5)", LenarValue::Bool(true)),
+                ("gte(-1 0)", LenarValue::Bool(false)),
+                ("min(3 -2 2.5)", LenarValue::Integer(-2)),
+                ("max(3 -2 2.5)", LenarValue::Usize(3)),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap(), expected, "{code}");
             }
+        }
 
-            // NewList()
-            #[derive(Debug)]
-            struct NewListFunc;
+        #[test]
+        fn arithmetic_errors() {
+            let err = evaluate("add(18446744073709551615 1)").unwrap_err();
+            assert!(matches!(err.root(), LenarError::Overflow(operation) if operation == "add"));
 
-            impl RuntimeFunction for NewListFunc {
-                fn call(
-                    &mut self,
-                    args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    Ok(LenarValue::List(args))
-                }
+            let err = evaluate("pow(10.0 400)").unwrap_err();
+            assert!(matches!(err.root(), LenarError::Overflow(operation) if operation == "pow"));
 
-                fn get_name(&self) -> &str {
-                    "list"
-                }
+            for code in ["div(1 0)", "mod(1.5 0.0)"] {
+                let err = evaluate(code).unwrap_err();
+                assert!(matches!(err.root(), LenarError::DivisionByZero), "{code}");
             }
 
-            // iter()
-            #[derive(Debug)]
-            struct IterFunc {
-                resources_files: Rc<RefCell<Slab<File>>>,
+            for code in ["parseFloat(\"NaN\") < 1", "max(1 parseFloat(\"NaN\"))"] {
+                let err = evaluate(code).unwrap_err();
+                assert!(matches!(err.root(), LenarError::Unordered { .. }), "{code}");
             }
+            let err = evaluate("parseFloat(\"NaN\") < 1").unwrap_err();
+            assert_eq!(err.to_string(), "`NaN` and `1` can't be ordered");
+        }
 
-            impl IterFunc {
-                pub fn new(resources_files: Rc<RefCell<Slab<File>>>) -> Self {
-                    Self { resources_files }
-                }
+        #[test]
+        fn operator_precedence() {
+            let cases = [
+                ("1 + 2 * 3", LenarValue::Usize(7)),
+                ("(1 + 2) * 3", LenarValue::Usize(9)),
+                ("10 - 4 - 3", LenarValue::Usize(3)),
+                ("2 * 3 % 4", LenarValue::Usize(2)),
+                ("-2 * 3", LenarValue::Integer(-6)),
+                ("1 - -1", LenarValue::Usize(2)),
+                ("1 + 1 == 2 && 3 > 2", LenarValue::Bool(true)),
+                ("!true || 1 <= 0", LenarValue::Bool(false)),
+                ("!(1 != 1)", LenarValue::Bool(true)),
+                ("let x = 4; let y = x / 2 + 1; y", LenarValue::Usize(3)),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap(), expected, "{code}");
             }
+        }
 
-            impl RuntimeFunction for IterFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let iterator = args.remove(0);
-                    let fun = args.remove(0);
+        #[test]
+        fn logical_operators_short_circuit() {
+            assert_eq!(
+                evaluate("false && missing").unwrap(),
+                LenarValue::Bool(false)
+            );
+            assert_eq!(evaluate("true || missing").unwrap(), LenarValue::Bool(true));
+            assert!(evaluate("true && missing").is_err());
+        }
 
-                    if let LenarValue::Function(fun) = fun {
-                        let mut fun = fun.borrow_mut();
-                        match iterator {
-                            LenarValue::Usize(rid) => {
-                                let resources_files = self.resources_files.borrow_mut();
-                                let file = resources_files.get(rid).unwrap();
-                                let bytes = file.bytes();
+        #[test]
+        fn else_if_chains() {
+            let code =
+                "let sign = fn(n) [] { if (n > 0) { 1 } else if (n < 0) { -1 } else { 0 } };";
+            for (n, expected) in [
+                ("5", LenarValue::Usize(1)),
+                ("-5", LenarValue::Integer(-1)),
+                ("0", LenarValue::Usize(0)),
+            ] {
+                let code = format!("{code} sign({n})");
+                assert_eq!(evaluate(&code).unwrap(), expected, "{n}");
+            }
 
-                                for byte in bytes {
-                                    if let Ok(byte) = byte {
-                                        fun.call(vec![LenarValue::Byte(byte)], _parser)?;
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            }
-                            LenarValue::Bytes(bytes) => {
-                                for byte in bytes {
-                                    fun.call(vec![LenarValue::Byte(byte)], _parser)?;
-                                }
-                            }
-                            LenarValue::OwnedBytes(bytes) => {
-                                for byte in bytes {
-                                    fun.call(vec![LenarValue::Byte(byte)], _parser)?;
-                                }
-                            }
-                            LenarValue::List(items) => {
-                                for (i, item) in items.into_iter().enumerate() {
-                                    fun.call(vec![item, LenarValue::Usize(i)], _parser)?;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+            // Without a matching branch the result is void
+            assert_eq!(
+                evaluate("if (false) { 1 } else if (false) { 2 }").unwrap(),
+                LenarValue::Void
+            );
+        }
 
-                    Ok(LenarValue::Void)
-                }
+        #[test]
+        fn loops_break_and_continue() {
+            let code = "let i = ref(0); while (true) { add(i 1); if (i == 3) { break i * 10 } }";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(30));
+
+            let code = "let total = ref(0); for n in list(1 2 3 4) { if (n == 2) { continue } add(total n) }; total + 0";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(8));
+
+            // Strings are iterated character by character
+            let code = "let count = ref(0); for c in \"abc\" { add(count 1) }; count + 0";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(3));
+            let code = "let seen = ref([]); for c in \"hé\" { push(seen c) }; seen";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[h, é]");
+            let code = "let seen = ref([]); iter(concat(\"h\" \"é\") fn(c) { push(seen c) }); seen";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[h, é]");
+
+            // Loops that end without `break` are void
+            assert_eq!(
+                evaluate("for n in list(1) { n }").unwrap(),
+                LenarValue::Void
+            );
+            assert_eq!(evaluate("while (false) {}").unwrap(), LenarValue::Void);
+        }
 
-                fn get_name(&self) -> &str {
-                    "iter"
-                }
-            }
+        #[test]
+        fn loop_variables_stay_in_the_loop() {
+            let err = evaluate("for n in list(1) { let inner = n }; inner").unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "inner"));
 
-            // sleep()
-            #[derive(Debug)]
-            struct SleepFunc;
+            let err = evaluate("for n in 5 {}").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::TypeMismatch { expected, actual } if expected == "iterable" && actual == "integer"
+            ));
+        }
 
-            impl RuntimeFunction for SleepFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let v = args.remove(0);
-                    if let LenarValue::Usize(time) = v {
-                        thread::sleep(Duration::from_millis(time as u64));
-                    }
-                    Ok(LenarValue::Void)
-                }
+        #[test]
+        fn return_leaves_the_function() {
+            let code = "let find = fn(limit) [] { let i = ref(0); while (true) { add(i 1); if (i * i > limit) { return i + 0 } }; 0 }; find(50)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(8));
 
-                fn get_name(&self) -> &str {
-                    "sleep"
-                }
-            }
+            // Without a value the function returns void
+            let code = "let f = fn() [] { return; 1 }; f()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Void);
 
-            // Ok()
-            #[derive(Debug)]
-            struct OkFunc;
+            // Only the innermost function returns
+            let code = "let inner = fn() [] { return 1; 2 }; let outer = fn() [inner] { inner() + 10 }; outer()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(11));
+        }
 
-            impl RuntimeFunction for OkFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let v = args.remove(0);
-                    Ok(LenarValue::Enum(LenarEnum::new_with_variant(
-                        "Ok".to_string(),
-                        v,
-                    )))
-                }
+        #[test]
+        fn declared_functions_can_recurse() {
+            let code = "fn fib(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } } fib(10)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(55));
 
-                fn get_name(&self) -> &str {
-                    "Ok"
-                }
-            }
+            // Declarations can call the ones after them
+            let code = "fn isEven(n) { if (n == 0) { true } else { isOdd(n - 1) } } fn isOdd(n) { if (n == 0) { false } else { isEven(n - 1) } } isEven(10)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Bool(true));
 
-            // Err()
-            #[derive(Debug)]
-            struct ErrFunc;
+            // Declarations inside functions stay in them
+            let code = "fn outer() { fn helper() { 5 } helper() } outer() + 1";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(6));
+            let err = evaluate("fn outer() { fn helper() { 5 } 0 } helper()").unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "helper"));
+        }
 
-            impl RuntimeFunction for ErrFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let v = args.remove(0);
-                    Ok(LenarValue::Enum(LenarEnum::new_with_variant(
-                        "Err".to_string(),
-                        v,
-                    )))
-                }
+        #[test]
+        fn declared_functions_are_dropped() {
+            #[derive(Debug)]
+            struct Tracker(Rc<Cell<usize>>);
 
-                fn get_name(&self) -> &str {
-                    "Err"
+            impl Drop for Tracker {
+                fn drop(&mut self) {
+                    self.0.set(self.0.get() + 1);
                 }
             }
 
-            // isOk()
-            #[derive(Debug)]
-            struct IsOkFunc;
-
-            impl RuntimeFunction for IsOkFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let v = args.remove(0);
-                    match v {
-                        LenarValue::Enum(variants) => {
-                            let ok_variant = variants.peek_variant("Ok");
-                            Ok(LenarValue::Bool(ok_variant.is_some()))
-                        }
-                        _ => Ok(LenarValue::Bool(false)),
-                    }
+            impl RuntimeInstance for Tracker {
+                fn get_prop(&self, _prop: &str) -> LenarValue {
+                    LenarValue::Void
                 }
 
                 fn get_name(&self) -> &str {
-                    "isOk"
+                    "tracker"
                 }
             }
 
-            // unwrap()
-            #[derive(Debug)]
-            struct UnwrapFunc;
+            let drops = Rc::new(Cell::new(0));
+            let run = |code: &str| {
+                let parser = Parser::new(code).unwrap();
+                let mut scope = Scope::default();
+                scope.setup_globals();
+                scope.add_global_instance(Tracker(drops.clone()));
+                let execution = Runtime::run_with_scope(&mut scope, &parser);
+                assert_eq!(execution.result.unwrap(), LenarValue::Usize(0));
+            };
 
-            impl RuntimeFunction for UnwrapFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let value = args.remove(0);
-                    match value {
-                        LenarValue::Enum(variants) => {
-                            let variant = variants.get_variant("Ok");
-                            variant.ok_or_else(|| LenarError::WrongValue("Ok".to_owned()))
-                        }
-                        _ => Ok(LenarValue::Void),
-                    }
-                }
+            run("fn outer(t) { fn inner() { t } fn other() [t] { inner() } other() } outer(tracker); 0");
+            assert_eq!(drops.get(), 1);
 
-                fn get_name(&self) -> &str {
-                    "unwrap"
-                }
-            }
+            // Anonymous functions stored where they were created too
+            run("let outer = fn(t) { let inner = fn() { t }; let fns = [fn() { inner() }]; let first = fns[0]; first() }; outer(tracker); 0");
+            assert_eq!(drops.get(), 2);
 
-            // unwrapErr()
-            #[derive(Debug)]
-            struct UnwrapErrFunc;
+            // Functions can still call themselves once their scope is gone
+            let code = "fn counter() { fn c(n) { if (n == 0) { 0 } else { c(n - 1) + 1 } } c } let f = counter(); f(3)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(3));
 
-            impl RuntimeFunction for UnwrapErrFunc {
-                fn call(
-                    &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
-                ) -> LenarResult<LenarValue> {
-                    let value = args.remove(0);
-                    match value {
-                        LenarValue::Enum(variants) => {
-                            let variant = variants.get_variant("Err");
-                            variant.ok_or_else(|| LenarError::WrongValue("Err".to_owned()))
-                        }
-                        _ => Ok(LenarValue::Void),
-                    }
-                }
+            // And each other
+            let code = "let make = fn() { fn helper(x) { x + 1 } fn inner(x) { helper(x) } inner }; let f = make(); f(1)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(2));
+            let code = "let make = fn() { fn even(n) { if (n == 0) { true } else { odd(n - 1) } } fn odd(n) { if (n == 0) { false } else { even(n - 1) } } [even odd] }; let fns = make(); let odd = fns[1]; odd(7)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Bool(true));
+        }
 
-                fn get_name(&self) -> &str {
-                    "unwrapErr"
-                }
-            }
+        #[test]
+        fn closures_share_surrounding_variables() {
+            let code =
+                "let count = ref(0); let bump = fn() { add(count 1) }; bump(); bump(); count + 0";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(2));
+
+            // Variables outlive the function that declared them
+            let code = "fn scale(by) { fn(n) { n * by } } let triple = scale(3); triple(5)";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(15));
+
+            // A capture area limits what the function sees
+            let err = evaluate("let x = 1; let f = fn() [] { x }; f()").unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "x"));
+        }
+
+        #[test]
+        fn closures_see_later_definitions() {
+            let code = "let f = fn() { y }; let y = 1; f()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(1));
+            let code = "fn f() { g() } let g = fn() { 1 }; f()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(1));
 
-            // ref()
             #[derive(Debug)]
-            struct RefFunc;
+            struct HostFunc;
 
-            impl RuntimeFunction for RefFunc {
+            impl RuntimeFunction for HostFunc {
                 fn call(
                     &mut self,
-                    mut args: Vec<LenarValue>,
+                    _args: Vec<LenarValue>,
                     _parser: &Parser,
                 ) -> LenarResult<LenarValue> {
-                    let v = args.remove(0);
-                    Ok(LenarValue::Ref(Rc::new(RefCell::new(v))))
+                    Ok(LenarValue::Usize(7))
                 }
 
                 fn get_name(&self) -> &str {
-                    "ref"
+                    "hostFn"
                 }
             }
 
-            // add()
+            // Including those of later executions and the globals added afterwards
+            let mut parser = Parser::new("fn f() { g() + hostFn() }").unwrap();
+            let mut scope = Scope::default();
+            scope.setup_globals();
+            let execution = Runtime::run_with_scope(&mut scope, &parser);
+            parser.parse("let g = fn() { 1 }; f()").unwrap();
+            scope.add_global_function(HostFunc);
+            let execution =
+                Runtime::resume_execution(&mut scope, &parser, execution.scope_position);
+            assert_eq!(execution.result.unwrap(), LenarValue::Usize(8));
+        }
+
+        #[test]
+        fn closures_share_native_functions() {
             #[derive(Debug)]
-            struct AddFunc;
+            struct HostFunc;
 
-            impl RuntimeFunction for AddFunc {
+            impl RuntimeFunction for HostFunc {
                 fn call(
                     &mut self,
-                    mut args: Vec<LenarValue>,
+                    _args: Vec<LenarValue>,
                     _parser: &Parser,
                 ) -> LenarResult<LenarValue> {
-                    let value = args.remove(0);
-                    let increment = args.remove(0);
-
-                    let result = match value {
-                        LenarValue::Ref(value) => {
-                            let mut value = value.borrow_mut();
-                            let increment = increment.as_integer();
-                            if let Some(increment) = increment {
-                                value.set_integer(increment);
-
-                                if let Some(n) = value.as_integer() {
-                                    LenarValue::Usize(n)
-                                } else {
-                                    LenarValue::Void
-                                }
-                            } else {
-                                LenarValue::Void
-                            }
-                        }
-                        _ => LenarValue::Void,
-                    };
-                    Ok(result)
+                    Ok(LenarValue::Usize(7))
                 }
 
                 fn get_name(&self) -> &str {
-                    "add"
+                    "hostFn"
                 }
             }
 
-            // not()
+            let parser = Parser::new("fn f() { hostFn() } f()").unwrap();
+            let mut scope = Scope::default();
+            scope.setup_globals();
+            scope.add_global_function(HostFunc);
+            let execution = Runtime::run_with_scope(&mut scope, &parser);
+            assert_eq!(execution.result.unwrap(), LenarValue::Usize(7));
+
+            // Functions with a capture area see the globals too
+            let parser = Parser::new("let f = fn() [] { hostFn() }; f()").unwrap();
+            let execution = Runtime::run_with_scope(&mut scope, &parser);
+            assert_eq!(execution.result.unwrap(), LenarValue::Usize(7));
+
+            // Aliases of builtins too, and callbacks can still call the running builtins
+            let code =
+                "let m = map; let g = fn(x) { m([1] fn(y) { map([y] fn(z) { z + x }) }) }; g(1)";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[[2]]");
+            let code = "let m = map; m([1 2] fn(x) { m([3] fn(y) { y + x }) })";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[[4], [5]]");
+            let code = "let n = ref(0); let it = iter; it([1 2] fn(x) { it([3] fn(y) { add(n y) }) }); n + 0";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(6));
+            let code =
+                "let s = sort; s([3 1 2] fn(a b) { len(s([b a] fn(c d) { c - d })) * (a - b) })";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[1, 2, 3]");
+
+            // Host functions can't be called while they run
             #[derive(Debug)]
-            struct NotFunc;
+            struct ApplyFunc;
 
-            impl RuntimeFunction for NotFunc {
+            impl RuntimeFunction for ApplyFunc {
                 fn call(
                     &mut self,
-                    mut args: Vec<LenarValue>,
-                    _parser: &Parser,
+                    args: Vec<LenarValue>,
+                    parser: &Parser,
                 ) -> LenarResult<LenarValue> {
-                    let cond = args.remove(0);
-
-                    let result = match cond {
-                        LenarValue::Bool(cond) => LenarValue::Bool(!cond),
-                        _ => LenarValue::Void, // TODO: Should throw error.
-                    };
-                    Ok(result)
+                    let function = args[0].as_func().unwrap();
+                    call_runtime_function(&function, Vec::new(), parser)
                 }
 
                 fn get_name(&self) -> &str {
-                    "not"
+                    "apply"
                 }
             }
 
-            self.variables.insert(
-                "not".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(NotFunc))),
-            );
-            self.variables.insert(
-                "add".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(AddFunc))),
-            );
-            self.variables.insert(
-                "ref".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(RefFunc))),
-            );
-            self.variables.insert(
-                "unwrapErr".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(UnwrapErrFunc))),
-            );
-            self.variables.insert(
-                "unwrap".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(UnwrapFunc))),
-            );
-            self.variables.insert(
-                "Err".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(ErrFunc))),
-            );
-            self.variables.insert(
-                "isOk".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(IsOkFunc))),
-            );
-            self.variables.insert(
-                "Ok".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(OkFunc))),
-            );
-            self.variables.insert(
-                "sleep".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(SleepFunc))),
-            );
-            self.variables.insert(
-                "list".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(NewListFunc))),
-            );
-            self.variables.insert(
-                "iter".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(IterFunc::new(
-                    resources_files.clone(),
-                )))),
-            );
-            self.variables.insert(
-                "toString".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(ToStringFunc::new(
-                    resources_files.clone(),
-                )))),
-            );
-            self.variables.insert(
-                "openFile".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(OpenFileFunc::new(resources_files)))),
-            );
-            self.variables.insert(
-                "print".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(PrintFunc))),
-            );
-            self.variables.insert(
-                "println".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(PrintLnFunc))),
-            );
-            self.variables.insert(
-                "Lenar".to_string(),
-                LenarValue::Instance(Rc::new(RefCell::new(LenarGlobal))),
-            );
-            self.variables.insert(
-                "isEqual".to_string(),
-                LenarValue::Function(Rc::new(RefCell::new(IsEqual))),
-            );
+            let parser = Parser::new("fn f() { apply(fn() { apply(fn() { 1 }) }) } f()").unwrap();
+            let mut scope = Scope::default();
+            scope.setup_globals();
+            scope.add_global_function(ApplyFunc);
+            let execution = Runtime::run_with_scope(&mut scope, &parser);
+            assert!(matches!(
+                execution.result.unwrap_err().root(),
+                LenarError::AlreadyRunning
+            ));
         }
 
-        /// Get a mutable handle scope to the desired [`Scope`]
-        pub fn get_scope(&mut self, path: &mut Iter<usize>) -> &mut Scope {
-            let scope = path.next();
+        #[test]
+        fn assignment_and_compound_assignment() {
+            let code = "let x = 1; x = 5; x += 2; x *= 3; x -= 30; x %= 5; x";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Integer(-4));
 
-            if let Some(scope) = scope {
-                self.scopes.get_mut(scope).unwrap().get_scope(path)
-            } else {
-                self
+            // Functions update the variables they share
+            let code = "let n = 0; let inc = fn() { n += 1 }; inc(); inc(); n";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(2));
+
+            // The value behind a reference is updated
+            let code = "let r = ref(1); let alias = r; r = 7; alias + 0";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(7));
+
+            // Indexes and properties are read and written in the same place
+            let code = "let xs = [10 20]; let i = ref(0); xs[add(i 1) - 1] *= 3; xs[add(i 1) - 2] -= 5; [xs i]";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[[25, 20], 2]");
+            let code = r#"let m = { "a": 1 }; m["a"] += 1; m["a"] %= 2; m"#;
+            assert_eq!(evaluate(code).unwrap().to_string(), "{a: 0}");
+            let code = "struct P { x } let p = P(2); p.x *= 5; p.x -= 1; p.x";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(9));
+
+            let err = evaluate("missing = 1").unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "missing"));
+
+            let err = evaluate("let x = 1; x /= 0").unwrap_err();
+            assert!(matches!(err.root(), LenarError::DivisionByZero));
+        }
+
+        #[test]
+        fn map_values() {
+            let code = r#"let m = { "b": 2, "a": 1 + 1 }; m = set(m "c" 3); m = remove(m "b"); m"#;
+            assert_eq!(evaluate(code).unwrap().to_string(), "{a: 2, c: 3}");
+
+            let cases = [
+                (r#"get({ "x": 1 } "x")"#, LenarValue::Usize(1)),
+                (r#"get({ "x": 1 } "y")"#, LenarValue::Void),
+                (r#"has({ "x": 1 } "x")"#, LenarValue::Bool(true)),
+                // Referenced maps are updated in place
+                (
+                    r#"let m = ref({:}); set(m "y" 2); get(m "y")"#,
+                    LenarValue::Usize(2),
+                ),
+                (
+                    r#"keys({ "b": 1, "a": 2 })"#,
+                    LenarValue::List(vec![
+                        LenarValue::Str("a".to_owned()),
+                        LenarValue::Str("b".to_owned()),
+                    ]),
+                ),
+                (
+                    r#"values({ "b": 1, "a": 2 })"#,
+                    LenarValue::List(vec![LenarValue::Usize(2), LenarValue::Usize(1)]),
+                ),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap(), expected, "{code}");
             }
         }
 
-        /// Get a mutable handle to the desired [`RuntimeFunction`]
-        pub fn get_function(
-            &mut self,
-            name: impl AsRef<str>,
-            path: &mut Iter<usize>,
-        ) -> Option<Rc<RefCell<dyn RuntimeFunction>>> {
-            let scope = path.next();
-            if let Some(scope) = scope {
-                let result = self
-                    .scopes
-                    .get_mut(scope)
-                    .unwrap()
-                    .get_function(name.as_ref(), path);
-                if result.is_some() {
-                    return result;
+        #[test]
+        fn list_indexes_and_slices() {
+            let cases = [
+                ("[1 2 3][1]", "2"),
+                ("let l = [[1 2] [3 4]]; l[1][0]", "3"),
+                ("[1 2 3 4][1:3]", "[2, 3]"),
+                ("[1 2 3][:1]", "[1]"),
+                ("[1 2 3][2:]", "[3]"),
+                ("[1 2 3][3:]", "[]"),
+                ("let l = [1 2 3]; l[0] = 9; l[2] += 1; l", "[9, 2, 4]"),
+                ("let l = [[1] [2]]; l[1][0] = 5; l", "[[1], [5]]"),
+                (r#"let m = { "a": [1] }; m["a"][0] = 2; m["a"]"#, "[2]"),
+                // Indexes are evaluated once
+                (
+                    "let xs = [1 2 3]; let i = ref(0); xs[add(i 1)] += 10; [xs i]",
+                    "[[1, 12, 3], 1]",
+                ),
+                (
+                    "let l = [[1] [2]]; let i = ref(0); l[add(i 1)][0] = 5; [l i]",
+                    "[[[1], [5]], 1]",
+                ),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
+            }
+        }
+
+        #[test]
+        fn indexes_out_of_range() {
+            let cases = [
+                ("[1 2][2]", 2),
+                ("let l = [1 2]; l[5] = 1", 5),
+                ("[1 2][1:3]", 3),
+                ("[1 2 3][2:1]", 2),
+            ];
+
+            for (code, expected_index) in cases {
+                let err = evaluate(code).unwrap_err();
+                assert!(
+                    matches!(
+                        err.root(),
+                        LenarError::IndexOutOfRange { index, .. } if *index == expected_index
+                    ),
+                    "{code}"
+                );
+            }
+        }
+
+        #[test]
+        fn list_library() {
+            let cases = [
+                ("map([1 2 3] fn(n) { n * 2 })", "[2, 4, 6]"),
+                ("map([5 6] fn(n i) { i })", "[0, 1]"),
+                ("filter(range(0 10) fn(n) { n % 3 == 0 })", "[0, 3, 6, 9]"),
+                ("reduce([1 2 3 4] fn(sum n) { sum + n })", "10"),
+                ("reduce([] fn(sum n) { sum + n } 100)", "100"),
+                ("find([1 2 3] fn(n) { n > 1 })", "2"),
+                (
+                    "[any([1 2] fn(n) { n > 1 }) all([1 2] fn(n) { n > 1 })]",
+                    "[true, false]",
+                ),
+                ("len([1 2 3])", "3"),
+                (
+                    "let l = ref([1]); push(l 2 3); let last = pop(l); [l last]",
+                    "[[1, 2], 3]",
+                ),
+                ("concat([1] [] [2 3])", "[1, 2, 3]"),
+                ("reverse([1 2 3])", "[3, 2, 1]"),
+                ("sort([3 -1 2.5 0])", "[-1, 0, 2.5, 3]"),
+                ("sort([\"b\" \"c\" \"a\"])", "[a, b, c]"),
+                ("sort([1 3 2] fn(a b) { b - a })", "[3, 2, 1]"),
+                ("zip([1 2 3] [4 5])", "[[1, 4], [2, 5]]"),
+                ("enumerate([7 8])", "[[0, 7], [1, 8]]"),
+                ("range(10 0 -4)", "[10, 6, 2]"),
+                ("range(0 1 0.25)", "[0, 0.25, 0.5, 0.75]"),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
+            }
+
+            // Errors in the callbacks stop the whole operation
+            let err = evaluate("map([1 2] fn(n) { missing })").unwrap_err();
+            assert!(matches!(err.root(), LenarError::VariableNotFound(name) if name == "missing"));
+        }
+
+        #[test]
+        fn ranges_are_limited() {
+            let err = evaluate("range(0 10000000000)").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::ResourceLimitExceeded {
+                    limit: MAX_RANGE_LEN,
+                    ..
                 }
+            ));
+            assert_eq!(
+                evaluate("len(range(0 1000000))").unwrap(),
+                LenarValue::Usize(MAX_RANGE_LEN)
+            );
+        }
+
+        #[test]
+        fn sort_with_bad_comparators() {
+            // Inconsistent comparators give some order instead of a panic
+            let code =
+                "let i = ref(0); len(sort(range(0 1000) fn(a b) { add(i 7); sub(mod(i 3) 1) }))";
+            assert_eq!(evaluate(code).unwrap().to_string(), "1000");
+
+            // The first failed comparison is the last one
+            let code = "let calls = ref(0); try { sort([3 1 2] fn(a b) { add(calls 1); missing }) } catch (e) {}; calls";
+            assert_eq!(evaluate(code).unwrap().to_string(), "1");
+            let err = evaluate("sort([1 \"a\" 2] fn(a b) { a - b })").unwrap_err();
+            assert!(matches!(err.root(), LenarError::TypeMismatch { .. }));
+        }
+
+        #[test]
+        fn string_library() {
+            let cases = [
+                ("len(\"héllo\")", "5"),
+                ("concat(\"ab\" \"\" \"c\")", "abc"),
+                ("split(\"a,b,,c\" \",\")", "[a, b, , c]"),
+                ("join([\"a\" 1 true] \"-\")", "a-1-true"),
+                ("trim(\"  x \")", "x"),
+                ("replace(\"a.b.c\" \".\" \"/\")", "a/b/c"),
+                (
+                    "[contains(\"abc\" \"bc\") startsWith(\"abc\" \"b\") endsWith(\"abc\" \"c\")]",
+                    "[true, false, true]",
+                ),
+                ("[toUpper(\"ñu\") toLower(\"ÑU\")]", "[ÑU, ñu]"),
+                ("substring(\"héllo\" 1 3)", "él"),
+                ("substring(\"héllo\" 2)", "llo"),
+                ("chars(\"né\")", "[n, é]"),
+                ("parseInt(\" -12 \") + 2", "-10"),
+                ("parseFloat(\"2.5\") * 2", "5"),
+                ("formatNumber(2 3)", "2.000"),
+                ("formatNumber(-3)", "-3"),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
             }
 
-            let variable = self.variables.get(name.as_ref())?;
-            variable.as_func()
+            let err = evaluate("substring(\"abc\" 1 4)").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::IndexOutOfRange { index: 4, len: 3 }
+            ));
+            assert!(evaluate("parseInt(\"1.5\")").is_err());
         }
 
-        /// Call a function given a name, a scope ID and arguments
-        pub fn call_function(
-            &mut self,
-            name: impl AsRef<str>,
-            path: &mut Iter<usize>,
-            args: Vec<LenarValue>,
-            parser: &Parser,
-        ) -> LenarResult<LenarValue> {
-            let func_name = name.as_ref().to_string();
-            let func = self.get_function(name, path);
+        #[test]
+        fn strings_compare_across_their_variants() {
+            let text = LenarValue::Str("abc".to_owned());
+            assert_eq!(text, LenarValue::OwnedBytes(b"abc".to_vec()));
+            assert_eq!(LenarValue::Bytes(b"abc".to_vec()), text);
+            assert_ne!(text, LenarValue::Str("abd".to_owned()));
 
-            if let Some(func) = func {
-                let mut func = func.borrow_mut();
-                func.call(args, parser)
-            } else {
-                Err(LenarError::VariableNotFound(func_name))
+            assert_eq!(
+                evaluate("\"a\" == concat(\"a\" \"\")").unwrap(),
+                LenarValue::Bool(true)
+            );
+        }
+
+        #[test]
+        fn template_strings() {
+            let cases = [
+                (r#"let name = "lenar"; "hi ${name}!""#, "hi lenar!"),
+                (r#""${1 + 2} = ${"three"}""#, "3 = three"),
+                (r#""${[1 2]} ${{ "a": 1 }["a"]}""#, "[1, 2] 1"),
+                (r#""outer ${"inner ${2 * 2}"}""#, "outer inner 4"),
+                (r#""\${escaped} \"${1}\"""#, "${escaped} \"1\""),
+                (r#""no embedding""#, "no embedding"),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
             }
         }
 
-        /// Define a variable with a given name and a value in the specified scope ID
-        pub fn define_variable(
-            &mut self,
-            name: impl AsRef<str>,
-            scope_path: &[usize],
-            value: LenarValue,
-        ) {
-            let scope = self.get_scope(&mut scope_path.iter());
-            scope.variables.insert(name.as_ref().to_string(), value);
+        #[test]
+        fn struct_fields() {
+            let code = "struct Point { x y } struct Line { from to } let l = Line(Point(1 2) Point(3 4)); l.to.x = l.from.y + 10; [l.from.x l.to.x l.to.y]";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[1, 12, 4]");
+
+            // Instances are shared, not copied
+            let code = "struct P { x } let a = P(1); let b = a; b.x += 1; a.x";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(2));
+
+            // Captured instances are behind a reference
+            let code = "struct P { x } let p = P(1); let f = fn() [p] { p.x = p.x + 4; p.x }; f()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(5));
+
+            // Missing fields read as void
+            let code = "struct P { x } let a = P(1); a.missing";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Void);
+
+            // Only instances have properties
+            for code in [
+                "struct P { x } let a = P(1); a.x.y",
+                "struct P { x } let a = P(1); a.x.y = 2",
+            ] {
+                let err = evaluate(code).unwrap_err();
+                assert!(
+                    matches!(
+                        err.root(),
+                        LenarError::TypeMismatch { expected, actual } if expected == "instance" && actual == "integer"
+                    ),
+                    "{code}"
+                );
+            }
+
+            let err = evaluate("struct P { x } let a = P(1); a.y = 2").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::PropertyNotAssignable { holder, property } if holder == "P" && property == "y"
+            ));
+            assert!(evaluate("struct P { x y } P(1)").is_err());
         }
 
-        /// Resolve a variable value given it's name and the caller scope ID
-        pub fn get_variable(
-            &mut self,
-            name: impl AsRef<str>,
-            path: &mut Iter<usize>,
-        ) -> LenarResult<LenarValue> {
-            let scope = path.next();
+        #[test]
+        fn match_enum_variants() {
+            let area = "enum Shape { Circle(r) Rect(w h) Empty } fn area(s) { match s { Circle(r) => 3 * r * r, Rect(w h) => w * h, Empty => 0 } }";
+            let cases = [
+                ("area(Circle(2))", "12"),
+                ("area(Rect(2 5))", "10"),
+                ("area(Empty)", "0"),
+                (
+                    "[Circle(1) Rect(1 2) Empty]",
+                    "[Circle(1), Rect([1, 2]), Empty]",
+                ),
+            ];
+            for (code, expected) in cases {
+                let code = format!("{area} {code}");
+                assert_eq!(evaluate(&code).unwrap().to_string(), expected, "{code}");
+            }
+        }
 
-            if let Some(scope) = scope {
-                let result = self
-                    .scopes
-                    .get_mut(scope)
-                    .unwrap()
-                    .get_variable(name.as_ref(), path);
-                if let Ok(result) = result {
-                    if !result.is_void() {
-                        return Ok(result);
-                    }
-                }
+        #[test]
+        fn match_literals_and_wildcards() {
+            let name = "fn name(n) { match n { 1 => \"one\", \"two\" => 2, _ => \"many\" } }";
+            let cases = [
+                ("name(1)", "one"),
+                ("name(\"two\")", "2"),
+                ("name(3)", "many"),
+                // Without a matching arm the value is void
+                ("match 1 { 2 => 3 }", "Void"),
+                // Bindings named `_` are ignored
+                ("enum E { P(a b) } match P(1 2) { P(_ b) => b }", "2"),
+            ];
+            for (code, expected) in cases {
+                let code = format!("{name} {code}");
+                assert_eq!(evaluate(&code).unwrap().to_string(), expected, "{code}");
             }
+        }
 
-            // Currently referencing a variable clones it's value,
-            // Once I add proper value-movements I will do this by calling
-            // `variables.remove(name.as_ref())` and without the `clone()`
-            // This way the variable's owned value will get removed from the scope folder
-            // and returned to the variable referencer
-            let var_name = name.as_ref().to_owned();
-            let var = self.variables.get(name.as_ref());
-            if let Some(var) = var {
-                Ok(var.clone())
-            } else {
-                Err(LenarError::VariableNotFound(var_name))
+        #[test]
+        fn options_and_results() {
+            let cases = [
+                ("Some(1)", "Some(1)"),
+                (
+                    "[isSome(Some(1)) isNone(None) isSome(None)]",
+                    "[true, true, false]",
+                ),
+                ("map(Some(2) fn(n) { n * 10 })", "Some(20)"),
+                ("map(None fn(n) { n * 10 })", "None"),
+                ("map(Err(\"e\") fn(n) { n * 10 })", "Err(e)"),
+                ("unwrapOr(None 5)", "5"),
+                ("unwrapOr(Ok(1) 5)", "1"),
+                (
+                    "andThen(Some(4) fn(n) { if (n > 3) { Some(n) } else { None } })",
+                    "Some(4)",
+                ),
+                ("andThen(Ok(1) fn(n) { Err(\"no\") })", "Err(no)"),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
             }
         }
 
-        pub fn get_variable_by_path(
-            &mut self,
-            var_path: &[String],
-            path: &mut Iter<usize>,
-        ) -> LenarResult<LenarValue> {
-            let scope = path.next();
-            if let Some(scope) = scope {
-                let result = self
-                    .scopes
-                    .get_mut(scope)
-                    .unwrap()
-                    .get_variable_by_path(var_path, path);
-                if let Ok(result) = result {
-                    if !result.is_void() {
-                        return Ok(result);
-                    }
-                }
+        #[test]
+        fn question_mark_returns_early() {
+            let half = "fn half(n) { if (n % 2 == 0) { Ok(n / 2) } else { Err(\"odd\") } } fn quarter(n) { let h = half(n)?; Ok(half(h)? + 0) }";
+            let cases = [
+                ("quarter(8)", "Ok(2)"),
+                ("quarter(6)", "Err(odd)"),
+                ("quarter(5)", "Err(odd)"),
+                ("fn f() { let v = None?; Some(v) } f()", "None"),
+            ];
+            for (code, expected) in cases {
+                let code = format!("{half} {code}");
+                assert_eq!(evaluate(&code).unwrap().to_string(), expected, "{code}");
             }
 
-            let mut var_path = var_path.iter();
+            // `?` is only allowed inside functions
+            assert!(Parser::new("Ok(1)?").is_err());
+        }
 
-            let var_holder = var_path.next().unwrap();
-            if let Some(LenarValue::Instance(instance)) = self.variables.get(var_holder) {
-                let instance = instance.borrow_mut();
-                Ok(instance.get_props(&mut var_path))
-            } else {
-                Err(LenarError::VariableNotFound(var_holder.clone()))
+        #[test]
+        fn catch_runtime_errors() {
+            let cases = [
+                ("try { 1 } catch (e) { 2 }", "1"),
+                ("try { missing } catch (e) { e.kind }", "VariableNotFound"),
+                ("try { div(1 0) } catch (e) { e.kind }", "DivisionByZero"),
+                ("try { throw(\"boom\") } catch (e) { [e.kind e.value] }", "[UserThrown, boom]"),
+                ("fn f() { throw(5) } try { f() } catch (e) { e.value + 1 }", "6"),
+                // Errors in the catch block go to the outer one
+                ("try { try { throw(1) } catch (e) { throw(e.value + 1) } } catch (e) { e.value }", "2"),
+            ];
+
+            for (code, expected) in cases {
+                assert_eq!(evaluate(code).unwrap().to_string(), expected, "{code}");
             }
+
+            let message = evaluate("try { missing } catch (e) { e.message }").unwrap();
+            assert!(message.to_string().contains("missing"));
+            assert!(evaluate("throw(1)").is_err());
         }
 
-        /// Create a new scope given an ID in the specified scope by a path
-        pub fn create_scope(&mut self, scope_path: &[usize], scope_id: usize) {
-            let scope = self.get_scope(&mut scope_path.iter());
-            let new_scope = Scope::default();
+        #[test]
+        fn catch_lets_control_flow_through() {
+            let code =
+                "let n = 0; while (true) { try { n += 1; if (n == 3) { break } } catch (e) {} }; n";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(3));
 
-            scope.scopes.insert(scope_id, new_scope);
+            let code = "fn f() { try { return 1 } catch (e) { 2 }; 3 } f()";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(1));
         }
 
-        /// Drop a scope given an ID and a scope path
-        pub fn drop_scope(&mut self, scope_path: &[usize], scope_id: usize) {
-            let scope = self.get_scope(&mut scope_path.iter());
-            scope.scopes.remove(&scope_id);
+        #[test]
+        fn errors_display_their_cause_and_stack() {
+            let cases = [
+                (
+                    "1 + \"a\"",
+                    "TypeMismatch",
+                    "Type mismatch, expected `number` but found `string`",
+                ),
+                ("let x = 1; x()", "NotCallable", "`x` is not a function"),
+                (
+                    "[1][3]",
+                    "IndexOutOfRange",
+                    "Index 3 out of range for length 1",
+                ),
+            ];
+            for (code, kind, message) in cases {
+                let err = evaluate(code).unwrap_err();
+                assert_eq!(err.kind(), kind, "{code}");
+                assert_eq!(err.to_string(), message, "{code}");
+            }
+
+            let err =
+                evaluate("fn inner() { 1 + true }\nfn outer() { inner() }\nouter()").unwrap_err();
+            assert_eq!(err.kind(), "TypeMismatch");
+            assert_eq!(
+                err.to_string(),
+                "Type mismatch, expected `number` but found `bool`\nStack backtrace:\n   0: inner (closure) at file 0, line 2, column 14\n   1: outer (closure) at file 0, line 3, column 1"
+            );
+            assert!(std::error::Error::source(&err).is_some());
         }
-    }
 
-    /// Evaluate a [`ParserObject`] to a [`LenarValue`]
-    fn evaluate_object(
-        object: &ParserObject,
-        parser: &Parser,
-        scope: &mut Scope,
-        scope_path: &[usize],
-        scope_position: Option<usize>,
-    ) -> LenarResult<LenarValue> {
-        match object {
-            ParserObject::Block { objects } => {
-                if objects.is_empty() {
-                    return Ok(LenarValue::Void);
-                }
+        #[test]
+        fn functions_construct_structs() {
+            let code = "struct P { x } fn mk() { P(1) } let f = fn() { P(2) }; let a = mk(); let b = f(); [a.x b.x]";
+            assert_eq!(
+                evaluate(code).unwrap(),
+                LenarValue::List(vec![LenarValue::Usize(1), LenarValue::Usize(2)])
+            );
+        }
 
-                let scope_position = scope_position.unwrap_or(0);
-                let mut next_scope_id = scope_path.last().copied().unwrap_or(0);
+        #[test]
+        fn functions_construct_variants() {
+            let code = "enum S { C(r) Q } fn g() { C(2) } let h = fn() { Q }; [g() h()]";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[C(2), Q]");
+        }
 
-                for (i, tok) in objects[scope_position..objects.len()].iter().enumerate() {
-                    let is_last = i == objects.len() - 1;
-                    let tok = parser.get_object(*tok).unwrap();
-                    let res = if matches!(tok, ParserObject::Block { .. }) {
-                        next_scope_id += 1;
-                        // Create block scope
-                        scope.create_scope(scope_path, next_scope_id);
+        #[test]
+        fn errors_are_send_and_sync() {
+            fn assert_error<T: std::error::Error + Send + Sync + 'static>() {}
+            assert_error::<LenarError>();
+        }
 
-                        // Run the block expression in the new scope
-                        let scope_path = &[scope_path, &[next_scope_id]].concat();
-                        let return_val = evaluate_object(tok, parser, scope, scope_path, None);
+        #[test]
+        fn catch_gets_the_thrown_value() {
+            let code = r#"fn f() { throw({ "code": [1 2] }) } try { f() } catch (e) { e.value["code"][1] }"#;
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(2));
 
-                        // Remove the scope
-                        scope.drop_scope(scope_path, next_scope_id);
-                        return_val
-                    } else {
-                        // Run the expression in the inherited scope
-                        evaluate_object(tok, parser, scope, scope_path, None)
-                    };
+            // Struct instances are thrown as a map of their fields
+            let code = "struct E { code } fn f() { throw(E(404)) } try { f() } catch (e) { e.value[\"code\"] }";
+            assert_eq!(evaluate(code).unwrap(), LenarValue::Usize(404));
+            let code = "struct N { next } let n = N(0); n.next = n; try { throw(n) } catch (e) { e.value[\"next\"] }";
+            assert_eq!(evaluate(code).unwrap().to_string(), "N");
+        }
 
-                    // Return the returned value from the expression as result of this block
-                    if is_last {
-                        return res;
-                    }
-                }
+        const COUNTDOWN: &str = "fn c(n) { if (n == 0) { 0 } else { c(n - 1) + 1 } }";
 
-                Ok(LenarValue::Void)
-            }
-            ParserObject::VarDef {
-                var_name,
-                block_value,
-            } => {
-                let value = parser.get_object(*block_value).unwrap();
-                let res = evaluate_object(value, parser, scope, scope_path, None)?;
-                scope.define_variable(var_name, scope_path, res);
+        /// Run code with the given limit of nested evaluations
+        fn evaluate_recursion(code: String, max_evaluation_depth: usize) -> LenarResult<String> {
+            let parser = Parser::new(&code).unwrap();
+            let mut scope = Scope::default();
+            scope.setup_globals();
+            scope.set_max_evaluation_depth(max_evaluation_depth);
+            Runtime::run_with_scope(&mut scope, &parser)
+                .result
+                .map(|value| value.to_string())
+        }
 
-                Ok(LenarValue::Void)
-            }
-            ParserObject::FunctionCall { arguments, fn_name } => {
-                let value = parser.get_object(*arguments).unwrap();
-                let mut args = Vec::new();
-                if let ParserObject::Block { objects } = value {
-                    for tok in objects {
-                        let tok = parser.get_object(*tok).unwrap();
-                        let res = evaluate_object(tok, parser, scope, scope_path, None)?;
+        #[test]
+        fn recursion_up_to_the_default_limit() {
+            let code = format!("{COUNTDOWN} c(30)");
+            assert_eq!(
+                evaluate_recursion(code, DEFAULT_MAX_EVALUATION_DEPTH).unwrap(),
+                "30"
+            );
 
-                        args.push(res);
-                    }
-                }
+            let code = format!("{COUNTDOWN} c(100000)");
+            let err = evaluate_recursion(code, DEFAULT_MAX_EVALUATION_DEPTH).unwrap_err();
+            assert_eq!(err.kind(), "ResourceLimitExceeded");
+        }
 
-                scope.call_function(fn_name, &mut scope_path.iter(), args, parser)
-            }
-            ParserObject::StringVal { value } => Ok(LenarValue::Str(value.to_string())), // TODO: Optimize this
-            ParserObject::BytesVal { value } => Ok(LenarValue::Bytes(value.to_owned())), // TODO: Optimize this
-            ParserObject::VarRef { var_name } => {
-                scope.get_variable(var_name, &mut scope_path.iter())
-            }
-            ParserObject::PropertyRef { path } => {
-                scope.get_variable_by_path(path, &mut scope_path.iter())
+        #[test]
+        fn nesting_inside_calls_counts_towards_the_limit() {
+            let blocks = format!("{}f(n - 1){}", "{".repeat(200), "}".repeat(200));
+            let negations = format!("{}f(n - 1)", "-".repeat(250));
+
+            for (body, n) in [(blocks, 190), (negations, 150)] {
+                let code = format!("fn f(n) {{ if (n == 0) {{ 0 }} else {{ {body} }} }} f({n})");
+                let err = evaluate_recursion(code, DEFAULT_MAX_EVALUATION_DEPTH).unwrap_err();
+                assert_eq!(err.kind(), "ResourceLimitExceeded");
             }
-            ParserObject::FnDef {
-                arguments_block,
-                block_value,
-                capture_value,
-            } => {
-                let capture_area_value = parser.get_object(*capture_value);
-
-                let capture_area = {
-                    let mut capture_area = HashMap::default();
-                    if let Some(ParserObject::Block { objects }) = capture_area_value {
-                        for object_key in objects {
-                            let object_value = parser.get_object(*object_key);
-                            if let Some(ParserObject::VarRef { var_name }) = object_value {
-                                let var_value =
-                                    scope.get_variable(var_name, &mut scope_path.iter())?;
-                                capture_area.insert(
-                                    var_name.clone(),
-                                    LenarValue::Ref(Rc::new(RefCell::new(var_value))),
-                                );
-                            }
-                        }
-                    }
-                    capture_area
-                };
 
-                // Anonymous function created at runtime
-                #[derive(Debug)]
-                struct Function {
-                    capture_area: HashMap<String, LenarValue>,
-                    arguments_block: usize,
-                    block_value: usize,
-                }
-
-                impl RuntimeFunction for Function {
-                    fn call(
-                        &mut self,
-                        mut args: Vec<LenarValue>,
-                        parser: &Parser,
-                    ) -> LenarResult<LenarValue> {
-                        // Anonymous functions do not capture any values by default.
-                        let mut scope = Scope::default();
-
-                        scope.setup_globals();
-
-                        // Define each argument as a variable in the function scope
-                        let arguments_block = parser.get_object(self.arguments_block).unwrap();
-                        if let ParserObject::Block { objects } = arguments_block {
-                            for object in objects {
-                                let arg_object = parser.get_object(*object).unwrap();
-                                if let ParserObject::VarRef { var_name } = arg_object {
-                                    let arg_value = args.remove(0);
-                                    scope.variables.insert(var_name.to_owned(), arg_value);
-                                }
-                            }
-                        }
+            // Callbacks of builtins take the most stack
+            let code = "fn f(n) { map([n] fn(x) { f(x + 1) }) } f(0)".to_owned();
+            let err = evaluate_recursion(code, DEFAULT_MAX_EVALUATION_DEPTH).unwrap_err();
+            assert_eq!(err.kind(), "ResourceLimitExceeded");
+        }
 
-                        // Inject every captured value in the function scope
-                        for (captured_var, value) in self.capture_area.iter() {
-                            scope
-                                .variables
-                                .insert(captured_var.to_owned(), value.clone());
-                        }
+        #[test]
+        fn max_evaluation_depth_is_configurable() {
+            let code = format!("{COUNTDOWN} c(3)");
+            assert_eq!(evaluate_recursion(code, 50).unwrap(), "3");
 
-                        let block_object = parser.get_object(self.block_value).unwrap();
+            let code = format!("{COUNTDOWN} try {{ c(20) }} catch (e) {{ e.kind }}");
+            assert_eq!(
+                evaluate_recursion(code, 50).unwrap(),
+                "ResourceLimitExceeded"
+            );
+        }
 
-                        evaluate_object(block_object, parser, &mut scope, &[], None)
-                    }
+        #[test]
+        fn missing_arguments_are_errors() {
+            for call in [
+                "Ok()",
+                "Err()",
+                "isOk()",
+                "unwrap()",
+                "unwrapErr()",
+                "ref()",
+                "not()",
+                "sleep()",
+                "iter(1)",
+                "toString()",
+                "Some()",
+                "isSome()",
+                "isNone()",
+                "unwrapOr(None)",
+                "throw()",
+            ] {
+                let code = format!("try {{ {call} }} catch (e) {{ e.kind }}");
+                assert_eq!(
+                    evaluate(&code).unwrap(),
+                    LenarValue::Str("ArityMismatch".to_owned()),
+                    "{call}"
+                );
+            }
+        }
 
-                    fn get_name(&self) -> &str {
-                        "Anonymous"
-                    }
-                }
-                Ok(LenarValue::Function(Rc::new(RefCell::new(Function {
-                    capture_area,
-                    arguments_block: *arguments_block,
-                    block_value: *block_value,
-                }))))
+        #[test]
+        fn functions_take_the_arguments_they_declare() {
+            let err = evaluate("fn f(a) { a } f(1 2 3)").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::ArityMismatch { fn_name, expected: 1, actual: 3 } if fn_name == "f"
+            ));
+
+            // Builtins leave out the ones they give but aren't taken, like indexes
+            let code = "struct P { x } [map([1 2] fn(x) { x * 2 }) map([5 6] fn(x i) { i }) len(map([1] P))]";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[[2, 4], [0, 1], 1]");
+        }
+
+        #[test]
+        fn builtins_check_the_type_of_their_arguments() {
+            assert_eq!(evaluate("not(false)").unwrap(), LenarValue::Bool(true));
+            assert_eq!(evaluate("isOk(Err(1))").unwrap(), LenarValue::Bool(false));
+            assert_eq!(evaluate("isSome(None)").unwrap(), LenarValue::Bool(false));
+            assert_eq!(evaluate("unwrapOr(None 6)").unwrap(), LenarValue::Usize(6));
+            let code = "let r = ref(Some(2)); let e = ref(Err(3)); [unwrap(r) unwrapErr(e)]";
+            assert_eq!(evaluate(code).unwrap().to_string(), "[2, 3]");
+            for call in [
+                "not(5)",
+                "isOk(5)",
+                "isSome(5)",
+                "isNone(5)",
+                "unwrapOr(5 6)",
+                "unwrap(5)",
+                "unwrapErr(5)",
+                "unwrapErr(Some(1))",
+                "enum Shape { Circle(r) } unwrap(Circle(1))",
+            ] {
+                let err = evaluate(call).unwrap_err();
+                assert_eq!(err.kind(), "TypeMismatch", "{call}");
             }
-            ParserObject::IfDef {
-                condition_block: expr,
-                block_value,
-            } => {
-                let expr_object = parser.get_object(*expr).unwrap();
-                let expr_res = evaluate_object(expr_object, parser, scope, scope_path, None)?;
-
-                // If the condition expression returns a `true` it
-                // will evaluate the actual block
-                if LenarValue::Bool(true) == expr_res {
-                    let expr_body_object = parser.get_object(*block_value).unwrap();
-                    evaluate_object(expr_body_object, parser, scope, scope_path, None)
-                } else {
-                    Ok(LenarValue::Void)
-                }
+
+            let err = evaluate("len(5)").unwrap_err();
+            assert!(matches!(
+                err.root(),
+                LenarError::TypeMismatch { expected, .. } if expected == "list, string or map"
+            ));
+        }
+
+        #[test]
+        fn unknown_files_are_errors() {
+            for call in ["toString(5)", "iter(5 fn(byte) {})"] {
+                let err = evaluate(call).unwrap_err();
+                assert_eq!(err.kind(), "Io", "{call}");
             }
-            ParserObject::NumberVal { value } => Ok(LenarValue::Usize(*value)),
+        }
+
+        #[test]
+        fn functions_share_the_opened_files() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            let len = std::fs::metadata(path).unwrap().len() as usize;
+
+            // Files can also be opened while others are being read
+            let code = format!(
+                "let count = fn(file) [] {{ let n = ref(0); iter(file fn(byte) {{ openFile(\"{path}\"); add(n 1) }}); n + 0 }}; count(openFile(\"{path}\"))"
+            );
+            assert_eq!(evaluate(&code).unwrap(), LenarValue::Usize(len));
+        }
+
+        #[test]
+        fn format_number_limits_decimals() {
+            assert_eq!(
+                evaluate("formatNumber(1.5 2)").unwrap(),
+                LenarValue::Str("1.50".to_owned())
+            );
+            let err = evaluate("formatNumber(1.5 100000)").unwrap_err();
+            assert_eq!(err.kind(), "WrongValue");
+
+            // Integers keep every digit
+            let code = "[formatNumber(9007199254740993 2) formatNumber(-3 1) formatNumber(7 0)]";
+            assert_eq!(
+                evaluate(code).unwrap().to_string(),
+                "[9007199254740993.00, -3.0, 7]"
+            );
         }
     }
 }